@@ -1,13 +1,16 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
 use eventsource_stream::EventStream;
 use futures_core::future::BoxFuture;
 use futures_util::{FutureExt as _, StreamExt as _};
 use reqwest::header;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tauri::{async_runtime::Mutex, Manager as _, State};
 
-use crate::{plugin_sys::PluginCore, serde_obj::MessageEventPayload, tokenizer::*, utility::{get_response_token, prase_tool_call}};
+use crate::{conversation::{save_current, Conversation}, generation::{current_params, GenerationParams}, plugin_sys::PluginCore, profile::{BackendKind, ModelProfile}, secrets::resolve_secret, summary::maybe_summarize, serde_obj::{MessageEventPayload, ToolCallFn, ToolEventPayload, ToolStatus}, speech::speak_stream, tokenizer::*, utility::{generate_random_string, get_response_token, prase_tool_call}};
+
+pub const BACKEND_NAME: &str = "thedtvn-local-ai-helper.hf.space";
 
 async fn crate_client() -> reqwest::Client {
     let mut headers = header::HeaderMap::new();
@@ -21,7 +24,7 @@ async fn crate_client() -> reqwest::Client {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ReqestEventID {
-    pub data: Vec<String>,
+    pub data: Vec<Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -29,10 +32,11 @@ pub struct ResponseEventID {
     pub event_id: String,
 }
 
-async fn get_event_id(client: reqwest::Client, promt: String) -> reqwest::Result<String> {
-    let body = ReqestEventID { data: vec![promt] };
+// the space only takes the prompt, stop and max_tokens are applied by ReplyText while streaming
+async fn get_event_id(client: reqwest::Client, url: &str, promt: String) -> reqwest::Result<String> {
+    let body = ReqestEventID { data: vec![Value::String(promt)] };
     let req = client
-        .post("https://thedtvn-local-ai-helper.hf.space/call/predict")
+        .post(format!("{url}/call/predict"))
         .json(&body)
         .send()
         .await?;
@@ -42,97 +46,432 @@ async fn get_event_id(client: reqwest::Client, promt: String) -> reqwest::Result
 
 async fn get_response(
     client: reqwest::Client,
+    url: &str,
     event_id: String,
 ) -> reqwest::Result<impl futures_core::Stream<Item = reqwest::Result<bytes::Bytes>>> {
     let req = client
-        .get(format!(
-            "https://thedtvn-local-ai-helper.hf.space/call/predict/{event_id}"
-        ))
+        .get(format!("{url}/call/predict/{event_id}"))
         .send()
         .await?;
     Ok(req.bytes_stream())
 }
 
-// this is to make it can recursion async
-pub fn get_response_text(promt: String, app: tauri::AppHandle, id: String) -> BoxFuture<'static, ()> {
-    async move {
-        get_response_text_async(promt, app, id).await;
-    }.boxed()
+pub enum Reply {
+    Text(String),
+    // the raw [TOOL_CALLS] json of a rendered template
+    ToolCallText(String),
+    ToolCalls(Vec<ToolCallFn>),
 }
 
-async fn get_response_text_async(promt: String, app: tauri::AppHandle, messages_uuid: String) {
-    let client = crate_client().await;
-    let event_id = get_event_id(client.clone(), promt).await.unwrap();
-    let res = get_response(client, event_id).await.unwrap();
+// collects the reply text, cuts it at stop sequences and the token limit
+struct ReplyText<'a> {
+    params: &'a GenerationParams,
+    text: String,
+    tokens: usize,
+    // only for backends that stream exactly one token per piece and cannot limit the reply themselves
+    max_tokens: Option<usize>,
+    on_text: &'a mut (dyn FnMut(&str) + Send),
+}
+
+impl ReplyText<'_> {
+    // true once the reply has to stop
+    fn push(&mut self, piece: &str) -> bool {
+        self.tokens += 1;
+        self.text.push_str(piece);
+        let mut stopped = self.max_tokens.is_some_and(|max| self.tokens >= max);
+        if let Some(cut) = self.params.find_stop(&self.text) {
+            self.text.truncate(cut);
+            stopped = true;
+        }
+        (self.on_text)(&self.text);
+        stopped
+    }
+}
+
+async fn stream_gradio(
+    client: reqwest::Client,
+    url: &str,
+    promt: String,
+    reply: &mut ReplyText<'_>,
+) -> Result<Reply, String> {
+    let event_id = get_event_id(client.clone(), url, promt).await.map_err(|e| e.to_string())?;
+    let res = get_response(client, url, event_id).await.map_err(|e| e.to_string())?;
     let mut stream = EventStream::new(res);
     let mut is_tool_call = false;
-    let mut vec = Vec::new();
+    let mut tool_call = String::new();
     let mut index = 0;
     while let Some(event) = stream.next().await {
-        match event {
-            Ok(event) => {
-                if event.event != "generating" {
-                    break;
-                };
-                let token = get_response_token(event.data);
-                if token.special && index == 0 && token.text == "[TOOL_CALLS]" {
-                    is_tool_call = true;
-                    continue;
-                } else if token.special && token.text == "</s>" {
-                    break;
-                } else if token.special && vec!["<unk>"].contains(&token.text.as_str()) {
-                    let _ = app.emit_all(
-                        "message",
-                        MessageEventPayload {
-                            data: "Unknown ?".to_string(),
-                            uuid: messages_uuid.clone(),
-                        },
-                    );
-                    break;
-                }
-                index += 1;
-                vec.push(token.text);
-                if is_tool_call {
-                    continue;
-                }
-                app.emit_all(
-                    "message",
-                    MessageEventPayload {
-                        data: vec.clone().join(""),
-                        uuid: messages_uuid.clone(),
-                    },
-                )
-                .unwrap();
+        let Ok(event) = event else { break };
+        if event.event != "generating" {
+            break;
+        };
+        let token = get_response_token(event.data);
+        if token.special && index == 0 && token.text == "[TOOL_CALLS]" {
+            is_tool_call = true;
+            continue;
+        } else if token.special && token.text == "</s>" {
+            break;
+        } else if token.special && token.text == "<unk>" {
+            (reply.on_text)("Unknown ?");
+            break;
+        }
+        index += 1;
+        if is_tool_call {
+            tool_call.push_str(&token.text);
+            continue;
+        }
+        if reply.push(&token.text) {
+            break;
+        }
+    }
+    if is_tool_call {
+        return Ok(Reply::ToolCallText(tool_call));
+    }
+    Ok(Reply::Text(reply.text.clone()))
+}
+
+#[derive(Deserialize, Default)]
+struct ChatFunctionDelta {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    arguments: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatToolCallDelta {
+    #[serde(default)]
+    index: usize,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    function: Option<ChatFunctionDelta>,
+}
+
+#[derive(Deserialize, Default)]
+struct ChatDelta {
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Option<Vec<ChatToolCallDelta>>,
+}
+
+#[derive(Deserialize)]
+struct ChatChoice {
+    #[serde(default)]
+    delta: ChatDelta,
+}
+
+#[derive(Deserialize)]
+struct ChatChunk {
+    #[serde(default)]
+    choices: Vec<ChatChoice>,
+}
+
+// tool calls arrive in pieces keyed by their index, names and arguments are appended
+fn add_tool_call_deltas(calls: &mut Vec<(Option<String>, String, String)>, deltas: Vec<ChatToolCallDelta>) {
+    for call in deltas {
+        if calls.len() <= call.index {
+            calls.resize(call.index + 1, (None, String::new(), String::new()));
+        }
+        let entry = &mut calls[call.index];
+        if call.id.is_some() {
+            entry.0 = call.id;
+        }
+        let function = call.function.unwrap_or_default();
+        entry.1.push_str(&function.name.unwrap_or_default());
+        entry.2.push_str(&function.arguments.unwrap_or_default());
+    }
+}
+
+// the arguments are a json string split over many chunks, only the whole string parses
+fn finish_tool_calls(calls: Vec<(Option<String>, String, String)>) -> Result<Vec<ToolCallFn>, String> {
+    calls
+        .into_iter()
+        .map(|(id, name, arguments)| {
+            let arguments = if arguments.trim().is_empty() {
+                Default::default()
+            } else {
+                serde_json::from_str(&arguments).map_err(|e| format!("tool {} arguments: {}", name, e))?
+            };
+            Ok(ToolCallFn { name, arguments, call_id: Some(id.unwrap_or_else(|| generate_random_string(9))) })
+        })
+        .collect()
+}
+
+async fn stream_openai(
+    client: reqwest::Client,
+    profile: &ModelProfile,
+    messages: Vec<Value>,
+    tools: Vec<Value>,
+    api_key: Option<&str>,
+    reply: &mut ReplyText<'_>,
+) -> Result<Reply, String> {
+    let params = reply.params;
+    let mut body = serde_json::json!({
+        "model": profile.model,
+        "messages": messages,
+        "stream": true,
+    });
+    if !tools.is_empty() {
+        body["tools"] = Value::from(tools);
+    }
+    if let Some(temperature) = params.temperature {
+        body["temperature"] = serde_json::json!(temperature);
+    }
+    if let Some(top_p) = params.top_p {
+        body["top_p"] = serde_json::json!(top_p);
+    }
+    if let Some(max_tokens) = params.max_tokens {
+        body["max_tokens"] = serde_json::json!(max_tokens);
+    }
+    if !params.stop.is_empty() {
+        body["stop"] = serde_json::json!(params.stop);
+    }
+    let mut req = client.post(format!("{}/chat/completions", profile.url.trim_end_matches('/')));
+    if let Some(api_key) = api_key {
+        req = req.bearer_auth(api_key);
+    }
+    let res = req
+        .json(&body)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| e.to_string())?;
+    let mut stream = EventStream::new(res.bytes_stream());
+    let mut tool_calls: Vec<(Option<String>, String, String)> = Vec::new();
+    while let Some(event) = stream.next().await {
+        let event = event.map_err(|e| e.to_string())?;
+        if event.data.trim() == "[DONE]" {
+            break;
+        }
+        let chunk: ChatChunk = serde_json::from_str(&event.data).map_err(|e| e.to_string())?;
+        let Some(choice) = chunk.choices.into_iter().next() else { continue };
+        add_tool_call_deltas(&mut tool_calls, choice.delta.tool_calls.unwrap_or_default());
+        if let Some(content) = choice.delta.content.filter(|c| !c.is_empty()) {
+            if reply.push(&content) {
+                break;
             }
-            Err(e) => break,
         }
     }
-    let messages: State<Arc<Mutex<Vec<MessageType>>>> = app.state();
-    let mut messages = messages.lock().await; 
-    if !is_tool_call {
-        messages.push(MessageType::Assistant(AssistantMessage { content: vec.join("") }));
-        return;
+    if !tool_calls.is_empty() {
+        return Ok(Reply::ToolCalls(finish_tool_calls(tool_calls)?));
+    }
+    Ok(Reply::Text(reply.text.clone()))
+}
+
+// one reply from the profile backend, on_text gets the whole text so far
+pub async fn stream_reply(
+    prompt: Prompt,
+    params: &GenerationParams,
+    api_key: Option<&str>,
+    on_text: &mut (dyn FnMut(&str) + Send),
+) -> Result<Reply, String> {
+    let client = crate_client().await;
+    // a gradio event is one token, openai servers get max_tokens in the request and may send
+    // several tokens per chunk, so counting chunks there would cut the reply short
+    let max_tokens = match prompt.profile.backend {
+        BackendKind::Gradio => params.max_tokens.map(|max| max as usize),
+        BackendKind::OpenAi => None,
+    };
+    let mut reply = ReplyText { params, text: String::new(), tokens: 0, max_tokens, on_text };
+    match (prompt.profile.backend, prompt.body) {
+        (BackendKind::Gradio, PromptBody::Text(text)) => {
+            stream_gradio(client, prompt.profile.url.trim_end_matches('/'), text, &mut reply).await
+        }
+        (BackendKind::OpenAi, PromptBody::Chat { messages, tools }) => {
+            stream_openai(client, &prompt.profile, messages, tools, api_key, &mut reply).await
+        }
+        _ => Err(format!("profile {} has a template its backend cannot use", prompt.profile.name)),
     }
-    let tool_calls_r = prase_tool_call(vec.join(""));
+}
+
+// collect a whole reply without streaming it to the ui, used for background jobs
+pub async fn get_completion(
+    app: &tauri::AppHandle,
+    profile: ModelProfile,
+    messages: Vec<MessageType>,
+    system_prompt: Option<String>,
+) -> Result<String, String> {
+    let api_key = resolve_secret(app, profile.api_key.as_deref())?;
+    let body = render_prompt(&profile, messages, system_prompt, &[]);
+    let params = profile.params.clone();
+    match stream_reply(Prompt { profile, body }, &params, api_key.as_deref(), &mut |_| {}).await? {
+        Reply::Text(text) => Ok(text),
+        _ => Err("the model answered with a tool call".to_string()),
+    }
+}
+
+// this is to make it can recursion async, the result is the final answer or the error the chat shows
+pub fn get_response_text(prompt: Prompt, app: tauri::AppHandle, id: String) -> BoxFuture<'static, Result<String, String>> {
+    async move {
+        get_response_text_async(prompt, app, id).await
+    }.boxed()
+}
+
+fn emit_message(app: &tauri::AppHandle, uuid: &str, data: String) {
+    let payload = MessageEventPayload { data, uuid: uuid.to_string() };
+    crate::api_server::trigger(app, "message", &payload);
+    let _ = app.emit_all("message", payload);
+}
+
+async fn get_response_text_async(prompt: Prompt, app: tauri::AppHandle, messages_uuid: String) -> Result<String, String> {
+    let profile = prompt.profile.clone();
+    let params = current_params(&app, &profile.params).await;
+    let emit_app = app.clone();
+    let emit_uuid = messages_uuid.clone();
+    let mut on_text = move |text: &str| {
+        emit_message(&emit_app, &emit_uuid, text.to_string());
+        speak_stream(&emit_app, &emit_uuid, text, false);
+    };
+    let reply = match resolve_secret(&app, profile.api_key.as_deref()) {
+        Ok(api_key) => stream_reply(prompt, &params, api_key.as_deref(), &mut on_text).await,
+        Err(e) => Err(e),
+    };
+    let reply = match reply {
+        Ok(reply) => reply,
+        Err(e) => {
+            eprintln!("{} request error {}", profile.name, e);
+            let error = format!("Request to {} failed 😵 {}", profile.name, e);
+            emit_message(&app, &messages_uuid, error.clone());
+            return Err(error);
+        }
+    };
+    let conversation: State<Arc<Mutex<Conversation>>> = app.state();
+    let mut conversation = conversation.lock().await;
+    let tool_calls_r = match reply {
+        Reply::Text(text) => {
+            speak_stream(&app, &messages_uuid, &text, true);
+            conversation.push(MessageType::Assistant(AssistantMessage { content: text.clone(), model: Some(profile.model.clone()) }));
+            drop(conversation);
+            save_current(&app).await;
+            maybe_summarize(&app, &messages_uuid).await;
+            return Ok(text);
+        }
+        Reply::ToolCallText(text) => prase_tool_call(text).map_err(|e| e.to_string()),
+        Reply::ToolCalls(tool_calls) => Ok(tool_calls),
+    };
     if tool_calls_r.is_err() {
-        let _ = app.emit_all(
-            "message",
-            MessageEventPayload {
-                data: "Cannot parse tool call 😵".to_string(),
-                uuid: messages_uuid.clone(),
-            },
-        );
-        return;
+        let error = "Cannot parse tool call 😵".to_string();
+        emit_message(&app, &messages_uuid, error.clone());
+        return Err(error);
+    }
+    // every call gets an id so its card and its response always find each other
+    let mut tool_calls = tool_calls_r.unwrap();
+    for tool_call in &mut tool_calls {
+        tool_call.call_id.get_or_insert_with(|| generate_random_string(9));
     }
-    let tool_calls = tool_calls_r.unwrap();
     let tool_call_str = serde_json::to_string(&tool_calls).unwrap();
-    messages.push(MessageType::ToolCall(ToolCall { content: tool_call_str }));
-    let p_callbacks: State<PluginCore> = app.state();
+    conversation.push(MessageType::ToolCall(ToolCall { content: tool_call_str }));
     for tool_call in tool_calls {
-        let tool_response = p_callbacks.call_fn(&tool_call.name, tool_call.arguments.clone());
-        messages.push(MessageType::ToolResponse(ToolResponse { content: tool_response.to_value(), call_id: tool_call.call_id }));
+        let mut tool_event = ToolEventPayload {
+            uuid: messages_uuid.clone(),
+            call_id: tool_call.call_id.clone().unwrap_or_default(),
+            name: tool_call.name.clone(),
+            arguments: tool_call.arguments.clone(),
+            status: ToolStatus::Running,
+            result: None,
+            duration_ms: None,
+        };
+        crate::api_server::trigger(&app, "tool", &tool_event);
+        let _ = app.emit_all("tool", tool_event.clone());
+        let start = Instant::now();
+        let tool_app = app.clone();
+        let name = tool_call.name.clone();
+        let arguments = tool_call.arguments.clone();
+        // tools may block on files or the network so they stay off the async workers
+        let tool_response = tauri::async_runtime::spawn_blocking(move || {
+            tool_app.state::<PluginCore>().call_fn(&name, arguments)
+        })
+        .await
+        .unwrap_or_else(|_| Err("tool call panicked".to_string()));
+        let duration_ms = start.elapsed().as_millis() as u64;
+        let (status, content) = match tool_response {
+            Ok(value) => (ToolStatus::Success, value.to_value()),
+            Err(e) => (ToolStatus::Error, Value::String(e)),
+        };
+        tool_event.status = status;
+        tool_event.result = Some(content.clone());
+        tool_event.duration_ms = Some(duration_ms);
+        crate::api_server::trigger(&app, "tool", &tool_event);
+        let _ = app.emit_all("tool", tool_event);
+        conversation.push(MessageType::ToolResponse(ToolResponse {
+            content,
+            call_id: tool_call.call_id,
+            name: tool_call.name,
+            status,
+            duration_ms,
+        }));
+    }
+    let persona = conversation.persona.clone();
+    let messages = conversation.prompt_messages();
+    drop(conversation);
+    save_current(&app).await;
+    // the answer to the tool results comes from the model that called the tools
+    let prompt = build_prompt(&app, profile, persona, messages).await;
+    get_response_text(prompt, app.clone(), messages_uuid).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deltas(data: &str) -> Vec<ChatToolCallDelta> {
+        let chunk: ChatChunk = serde_json::from_str(data).unwrap();
+        chunk.choices.into_iter().next().unwrap().delta.tool_calls.unwrap_or_default()
+    }
+
+    #[test]
+    fn tool_call_arguments_split_across_chunks() {
+        let chunks = [
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_1","function":{"name":"calc","arguments":""}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"expr"}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":1,"id":"call_2","function":{"name":"now"}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"ession\": \"2+"}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"2\"}"}}]}}]}"#,
+        ];
+        let mut calls = Vec::new();
+        for chunk in chunks {
+            add_tool_call_deltas(&mut calls, deltas(chunk));
+        }
+        let calls = finish_tool_calls(calls).unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].name, "calc");
+        assert_eq!(calls[0].call_id.as_deref(), Some("call_1"));
+        assert_eq!(calls[0].arguments["expression"], "2+2");
+        assert_eq!(calls[1].name, "now");
+        assert!(calls[1].arguments.is_empty());
+    }
+
+    #[test]
+    fn unfinished_arguments_are_an_error() {
+        let mut calls = Vec::new();
+        add_tool_call_deltas(
+            &mut calls,
+            deltas(r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"name":"calc","arguments":"{\"a\":"}}]}}]}"#),
+        );
+        assert!(finish_tool_calls(calls).unwrap_err().starts_with("tool calc arguments"));
     }
-    let promt = tokenize_messages(messages.clone(), app.clone());
-    drop(messages);
-    get_response_text(promt, app.clone(), messages_uuid).await
-}
\ No newline at end of file
+
+    #[test]
+    fn stop_sequence_split_over_chunks_is_cut() {
+        let params = GenerationParams { stop: vec!["END".to_string()], max_tokens: Some(2), ..Default::default() };
+        let mut on_text = |_: &str| {};
+        // openai chunks are not counted, the server applies max_tokens
+        let mut reply = ReplyText { params: &params, text: String::new(), tokens: 0, max_tokens: None, on_text: &mut on_text };
+        assert!(!reply.push("several tokens in one chunk"));
+        assert!(!reply.push(" then EN"));
+        assert!(reply.push("D and more"));
+        assert_eq!(reply.text, "several tokens in one chunk then ");
+    }
+
+    #[test]
+    fn gradio_tokens_stop_at_the_limit() {
+        let params = GenerationParams { max_tokens: Some(2), ..Default::default() };
+        let mut on_text = |_: &str| {};
+        let mut reply = ReplyText { params: &params, text: String::new(), tokens: 0, max_tokens: Some(2), on_text: &mut on_text };
+        assert!(!reply.push("one"));
+        assert!(reply.push(" two"));
+        assert_eq!(reply.text, "one two");
+    }
+}
@@ -0,0 +1,437 @@
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use bytes::Bytes;
+use futures_util::StreamExt as _;
+use http_body_util::{combinators::BoxBody, BodyExt, Full, Limited, StreamBody};
+use hyper::{
+    body::{Frame, Incoming},
+    header,
+    server::conn::http1,
+    service::service_fn,
+    Method, Request, Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+use tauri::{async_runtime::Mutex, EventHandler, Manager as _, State};
+use tokio::{net::TcpListener, sync::mpsc};
+
+use crate::{
+    attachment::{self, Attachment},
+    commands::{self, generate_reply},
+    conversation::Conversation,
+    hotkey::new_chat,
+    paths::{data_dir, write_private},
+    profile::RouteTask,
+    tokenizer::{MessageType, UserMessage},
+    utility::generate_random_string,
+};
+
+const TOKEN_LEN: usize = 32;
+// images come base64 encoded, so the body can be larger than the attachment limits
+const MAX_BODY_BYTES: usize = 32 * 1024 * 1024;
+// events of a reply that the api passes on, the ui gets the same ones
+const REPLY_EVENTS: [&str; 2] = ["message", "tool"];
+
+// set once the port is bound, the server runs until the app exits
+static SERVING: AtomicBool = AtomicBool::new(false);
+
+type Body = BoxBody<Bytes, Infallible>;
+type ApiResult = Result<Response<Body>, (StatusCode, String)>;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ApiConfig {
+    // headless mode serves the api even when this is off
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_port")]
+    pub port: u16,
+}
+
+fn default_port() -> u16 {
+    8765
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self { enabled: false, port: default_port() }
+    }
+}
+
+impl ApiConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.port == 0 {
+            return Err("the api needs a port".to_string());
+        }
+        Ok(())
+    }
+}
+
+// clients read the token from this file, only the user can read it
+pub fn token_path() -> PathBuf {
+    data_dir().join("api-token")
+}
+
+// the same token survives restarts, so editor plugins only have to be set up once
+pub fn load_or_create_token(path: &Path) -> Result<String, String> {
+    if let Ok(token) = std::fs::read_to_string(path) {
+        let token = token.trim();
+        if token.len() >= TOKEN_LEN {
+            return Ok(token.to_string());
+        }
+    }
+    let token = generate_random_string(TOKEN_LEN);
+    write_private(path, token.as_bytes()).map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+    Ok(token)
+}
+
+// compares every byte so the time does not tell how much of the token was right
+pub fn authorized(authorization: Option<&str>, token: &str) -> bool {
+    let Some(given) = authorization.and_then(|value| value.strip_prefix("Bearer ")) else {
+        return false;
+    };
+    let given = given.trim().as_bytes();
+    given.len() == token.len()
+        && given
+            .iter()
+            .zip(token.as_bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    GetMessages,
+    NewMessage,
+    ListConversations,
+    NewConversation,
+    OpenConversation(String),
+    MethodNotAllowed,
+    NotFound,
+}
+
+pub fn route(method: &Method, path: &str) -> Route {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let (get, post) = match segments.as_slice() {
+        ["v1", "messages"] => (Route::GetMessages, Route::NewMessage),
+        ["v1", "conversations"] => (Route::ListConversations, Route::NewConversation),
+        ["v1", "conversations", id, "open"] => (Route::MethodNotAllowed, Route::OpenConversation(id.to_string())),
+        _ => return Route::NotFound,
+    };
+    match *method {
+        Method::GET => get,
+        Method::POST => post,
+        _ => Route::MethodNotAllowed,
+    }
+}
+
+fn full(status: StatusCode, content_type: &str, body: impl Into<Bytes>) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Full::new(body.into()).boxed())
+        .unwrap()
+}
+
+fn json_response<T: Serialize>(value: &T) -> ApiResult {
+    let body = serde_json::to_vec(value).map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(full(StatusCode::OK, "application/json", body))
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    full(status, "application/json", json!({ "error": message }).to_string())
+}
+
+async fn read_json<T: DeserializeOwned>(req: Request<Incoming>) -> Result<T, (StatusCode, String)> {
+    let body = Limited::new(req.into_body(), MAX_BODY_BYTES)
+        .collect()
+        .await
+        .map_err(|e| (StatusCode::PAYLOAD_TOO_LARGE, e.to_string()))?
+        .to_bytes();
+    serde_json::from_slice(&body).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+pub fn sse_frame(event: &str, data: &str) -> Bytes {
+    Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+}
+
+// emit_all only reaches the webviews, the api listens on the rust side, every token of a
+// reply passes here so nothing is serialized while the api is off
+pub fn trigger<S: Serialize>(app: &tauri::AppHandle, event: &str, payload: &S) {
+    if !SERVING.load(Ordering::SeqCst) {
+        return;
+    }
+    app.trigger_global(event, serde_json::to_string(payload).ok());
+}
+
+// passes on the events of one reply while it is written
+struct ReplyListener {
+    app: tauri::AppHandle,
+    handlers: Vec<EventHandler>,
+}
+
+impl ReplyListener {
+    fn new(app: &tauri::AppHandle, uuid: &str, sender: mpsc::UnboundedSender<Bytes>) -> Self {
+        let handlers = REPLY_EVENTS
+            .iter()
+            .map(|&name| {
+                let uuid = uuid.to_string();
+                let sender = sender.clone();
+                app.listen_global(name, move |event| {
+                    let Some(payload) = event.payload() else { return };
+                    let Ok(value) = serde_json::from_str::<serde_json::Value>(payload) else { return };
+                    if value["uuid"] != uuid.as_str() {
+                        return;
+                    }
+                    let _ = sender.send(sse_frame(name, payload));
+                })
+            })
+            .collect();
+        Self { app: app.clone(), handlers }
+    }
+}
+
+impl Drop for ReplyListener {
+    fn drop(&mut self) {
+        for handler in self.handlers.drain(..) {
+            self.app.unlisten(handler);
+        }
+    }
+}
+
+async fn get_messages(app: &tauri::AppHandle) -> ApiResult {
+    let messages = commands::get_messages(app.state())
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    json_response(&messages)
+}
+
+#[derive(Deserialize)]
+struct NewMessageBody {
+    prompt: String,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+    // answer with server sent events while the reply is written
+    #[serde(default)]
+    stream: bool,
+}
+
+// the same as new_message from the ui, the open chat window shows the question and the answer too
+async fn send_message(app: &tauri::AppHandle, body: NewMessageBody) -> Result<(), String> {
+    if body.prompt.trim().is_empty() {
+        return Err("prompt is empty".to_string());
+    }
+    attachment::check_total(&body.attachments)?;
+    let conversation: State<Arc<Mutex<Conversation>>> = app.state();
+    conversation.lock().await.push(MessageType::User(UserMessage {
+        content: body.prompt,
+        attachments: body.attachments,
+    }));
+    let _ = app.emit_all("conversation-changed", ());
+    Ok(())
+}
+
+// the answer of this reply, the open chat may already hold another one by the time it ends
+async fn answer(app: &tauri::AppHandle, id: &str) -> Result<String, String> {
+    let answer = generate_reply(app.clone(), id.to_string(), RouteTask::Chat).await;
+    let _ = app.emit_all("conversation-changed", ());
+    answer.map(|text| text.trim().to_string())
+}
+
+async fn new_message(app: &tauri::AppHandle, body: NewMessageBody) -> ApiResult {
+    let id = uuid::Uuid::new_v4().to_string();
+    let stream = body.stream;
+    send_message(app, body).await.map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if !stream {
+        let text = answer(app, &id).await.map_err(|e| (StatusCode::BAD_GATEWAY, e))?;
+        return json_response(&json!({ "id": id, "answer": text }));
+    }
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let listener = ReplyListener::new(app, &id, sender.clone());
+    let app = app.clone();
+    // a client that goes away does not stop the reply, the chat keeps it like any other
+    tauri::async_runtime::spawn(async move {
+        let result = answer(&app, &id).await;
+        drop(listener);
+        let frame = match result {
+            Ok(answer) => sse_frame("done", &json!({ "id": id, "answer": answer }).to_string()),
+            Err(e) => sse_frame("error", &json!({ "id": id, "error": e }).to_string()),
+        };
+        let _ = sender.send(frame);
+    });
+    let frames = futures_util::stream::poll_fn(move |cx| receiver.poll_recv(cx))
+        .map(|bytes| Ok::<_, Infallible>(Frame::data(bytes)));
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(BodyExt::boxed(StreamBody::new(frames)))
+        .unwrap())
+}
+
+#[derive(Serialize, Debug)]
+struct ConversationItem {
+    id: String,
+    title: Option<String>,
+    created_at: i64,
+    updated_at: i64,
+    messages: usize,
+    // the one get_messages and new messages use
+    current: bool,
+}
+
+impl ConversationItem {
+    fn new(conversation: &Conversation, current: bool) -> Self {
+        Self {
+            id: conversation.id.clone(),
+            title: conversation.title.clone(),
+            created_at: conversation.created_at,
+            updated_at: conversation.updated_at,
+            messages: conversation.active_path().len(),
+            current,
+        }
+    }
+}
+
+async fn list_conversations(app: &tauri::AppHandle) -> ApiResult {
+    let saved = tauri::async_runtime::spawn_blocking(Conversation::load_all)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let conversation: State<Arc<Mutex<Conversation>>> = app.state();
+    let current = conversation.lock().await.clone();
+    // the open chat can be newer than its file, or not saved at all
+    let mut items = vec![ConversationItem::new(&current, true)];
+    items.extend(
+        saved
+            .iter()
+            .filter(|c| c.id != current.id)
+            .map(|c| ConversationItem::new(c, false)),
+    );
+    items.sort_by_key(|item| std::cmp::Reverse(item.updated_at));
+    json_response(&items)
+}
+
+async fn current_id(app: &tauri::AppHandle) -> String {
+    let conversation: State<Arc<Mutex<Conversation>>> = app.state();
+    let id = conversation.lock().await.id.clone();
+    id
+}
+
+async fn new_conversation(app: &tauri::AppHandle) -> ApiResult {
+    new_chat(app).await;
+    json_response(&json!({ "id": current_id(app).await }))
+}
+
+async fn open_conversation(app: &tauri::AppHandle, id: String) -> ApiResult {
+    commands::open_conversation(app.clone(), id, None)
+        .await
+        .map_err(|e| (StatusCode::NOT_FOUND, e))?;
+    let _ = app.emit_all("conversation-changed", ());
+    json_response(&json!({ "id": current_id(app).await }))
+}
+
+async fn handle(app: tauri::AppHandle, token: Arc<String>, req: Request<Incoming>) -> Result<Response<Body>, Infallible> {
+    let authorization = req.headers().get(header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+    if !authorized(authorization, &token) {
+        return Ok(error_response(StatusCode::UNAUTHORIZED, "missing or wrong token"));
+    }
+    let result = match route(req.method(), req.uri().path()) {
+        Route::GetMessages => get_messages(&app).await,
+        Route::NewMessage => match read_json(req).await {
+            Ok(body) => new_message(&app, body).await,
+            Err(e) => Err(e),
+        },
+        Route::ListConversations => list_conversations(&app).await,
+        Route::NewConversation => new_conversation(&app).await,
+        Route::OpenConversation(id) => open_conversation(&app, id).await,
+        Route::MethodNotAllowed => Err((StatusCode::METHOD_NOT_ALLOWED, "method not allowed".to_string())),
+        Route::NotFound => Err((StatusCode::NOT_FOUND, "not found".to_string())),
+    };
+    Ok(result.unwrap_or_else(|(status, e)| error_response(status, &e)))
+}
+
+// only bound to the loopback address, other machines cannot reach it even with the token
+pub async fn serve(app: tauri::AppHandle, port: u16) -> Result<(), String> {
+    let token = Arc::new(load_or_create_token(&token_path())?);
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("cannot listen on {}: {}", addr, e))?;
+    println!("api listening on http://{}, token in {}", addr, token_path().display());
+    SERVING.store(true, Ordering::SeqCst);
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                eprintln!("api accept error {}", e);
+                continue;
+            }
+        };
+        let app = app.clone();
+        let token = token.clone();
+        tauri::async_runtime::spawn(async move {
+            let service = service_fn(move |req| handle(app.clone(), token.clone(), req));
+            if let Err(e) = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await {
+                eprintln!("api connection error {}", e);
+            }
+        });
+    }
+}
+
+pub fn spawn_serve(app: &tauri::AppHandle, port: u16) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = serve(app, port).await {
+            eprintln!("api error {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_and_token() {
+        assert_eq!(route(&Method::GET, "/v1/messages"), Route::GetMessages);
+        assert_eq!(route(&Method::POST, "/v1/messages/"), Route::NewMessage);
+        assert_eq!(route(&Method::GET, "/v1/conversations"), Route::ListConversations);
+        assert_eq!(route(&Method::POST, "/v1/conversations"), Route::NewConversation);
+        assert_eq!(
+            route(&Method::POST, "/v1/conversations/abc/open"),
+            Route::OpenConversation("abc".to_string())
+        );
+        assert_eq!(route(&Method::GET, "/v1/conversations/abc/open"), Route::MethodNotAllowed);
+        assert_eq!(route(&Method::DELETE, "/v1/messages"), Route::MethodNotAllowed);
+        assert_eq!(route(&Method::GET, "/messages"), Route::NotFound);
+
+        let token = "a".repeat(TOKEN_LEN);
+        assert!(authorized(Some(&format!("Bearer {}", token)), &token));
+        assert!(!authorized(Some(&token), &token));
+        assert!(!authorized(Some("Bearer aaa"), &token));
+        assert!(!authorized(Some(&format!("Bearer {}b", token)), &token));
+        assert!(!authorized(None, &token));
+    }
+
+    #[test]
+    fn token_is_kept_between_starts() {
+        let path = std::env::temp_dir().join(format!("rasast-api-token-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "short").unwrap();
+        let token = load_or_create_token(&path).unwrap();
+        assert_eq!(token.len(), TOKEN_LEN);
+        assert_eq!(load_or_create_token(&path).unwrap(), token);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,274 @@
+use std::path::{Path, PathBuf};
+
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+
+use crate::{paths::data_dir, tokenizer::UserMessage};
+
+// same limit as the read_text_file tool, bigger files are cut
+pub const MAX_TEXT_BYTES: usize = 64 * 1024;
+// all text attachments of one message together
+pub const MAX_TOTAL_TEXT_BYTES: usize = 256 * 1024;
+pub const MAX_FILE_BYTES: u64 = 20 * 1024 * 1024;
+pub const MAX_IMAGE_BYTES: u64 = 5 * 1024 * 1024;
+
+const IMAGE_TYPES: [(&str, &str); 6] = [
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("bmp", "image/bmp"),
+];
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachmentKind {
+    Text,
+    Pdf,
+    Image,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Attachment {
+    pub name: String,
+    pub kind: AttachmentKind,
+    // the text of text and pdf files, already cut to the limit
+    #[serde(default)]
+    pub content: String,
+    #[serde(default)]
+    pub truncated: bool,
+    #[serde(default)]
+    pub mime: Option<String>,
+    // images are copied into the attachments folder, this is the name of the copy
+    #[serde(default)]
+    pub file: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ImageUrl {
+    pub url: String,
+}
+
+// the content part format of chat completion apis
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+pub fn get_attachment_dir() -> PathBuf {
+    data_dir().join("attachments")
+}
+
+fn image_mime(extension: &str) -> Option<&'static str> {
+    IMAGE_TYPES
+        .iter()
+        .find(|(ext, _)| ext.eq_ignore_ascii_case(extension))
+        .map(|(_, mime)| *mime)
+}
+
+// cut at a char boundary
+fn truncate_text(text: String, max_bytes: usize) -> (String, bool) {
+    if text.len() <= max_bytes {
+        return (text, false);
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    (text[..end].to_string(), true)
+}
+
+// pdf-extract panics on some broken files instead of returning an error
+fn extract_pdf(path: &Path) -> Result<String, String> {
+    let path = path.to_path_buf();
+    match std::panic::catch_unwind(move || pdf_extract::extract_text(&path)) {
+        Ok(Ok(text)) => Ok(text),
+        Ok(Err(e)) => Err(format!("cannot read pdf: {}", e)),
+        Err(_) => Err("cannot read pdf".to_string()),
+    }
+}
+
+pub fn load_attachment(path: &Path) -> Result<Attachment, String> {
+    let meta = std::fs::metadata(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if !meta.is_file() {
+        return Err(format!("{} is not a file", path.display()));
+    }
+    if meta.len() > MAX_FILE_BYTES {
+        return Err(format!("{} is bigger than {} MB", path.display(), MAX_FILE_BYTES / 1024 / 1024));
+    }
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if let Some(mime) = image_mime(&extension) {
+        if meta.len() > MAX_IMAGE_BYTES {
+            return Err(format!("{} is bigger than {} MB", name, MAX_IMAGE_BYTES / 1024 / 1024));
+        }
+        // a copy so the chat still works after the original is moved or deleted
+        let dir = get_attachment_dir();
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let file = format!("{}.{}", uuid::Uuid::new_v4(), extension);
+        std::fs::copy(path, dir.join(&file)).map_err(|e| e.to_string())?;
+        return Ok(Attachment {
+            name,
+            kind: AttachmentKind::Image,
+            content: String::new(),
+            truncated: false,
+            mime: Some(mime.to_string()),
+            file: Some(file),
+        });
+    }
+    let (kind, text) = if extension == "pdf" {
+        (AttachmentKind::Pdf, extract_pdf(path)?)
+    } else {
+        let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+        match String::from_utf8(bytes) {
+            Ok(text) if !text.contains('\0') => (AttachmentKind::Text, text),
+            _ => return Err(format!("{} is not a text, pdf or image file", name)),
+        }
+    };
+    let (content, truncated) = truncate_text(text, MAX_TEXT_BYTES);
+    Ok(Attachment {
+        name,
+        kind,
+        content,
+        truncated,
+        mime: None,
+        file: None,
+    })
+}
+
+pub fn check_total(attachments: &[Attachment]) -> Result<(), String> {
+    let total: usize = attachments.iter().map(|a| a.content.len()).sum();
+    if total > MAX_TOTAL_TEXT_BYTES {
+        return Err(format!(
+            "attachments have more than {} KB of text",
+            MAX_TOTAL_TEXT_BYTES / 1024
+        ));
+    }
+    Ok(())
+}
+
+// text only templates get the files inlined after the message and a note for every image
+pub fn prompt_text(message: &UserMessage) -> String {
+    let mut text = message.content.clone();
+    for attachment in &message.attachments {
+        match attachment.kind {
+            AttachmentKind::Text | AttachmentKind::Pdf => {
+                text.push_str(&format!("\n\n--- file: {} ---\n{}", attachment.name, attachment.content));
+                if attachment.truncated {
+                    text.push_str("\n[file truncated]");
+                }
+                text.push_str(&format!("\n--- end of {} ---", attachment.name));
+            }
+            AttachmentKind::Image => {
+                text.push_str(&format!("\n\n[image {} attached, this model cannot see images]", attachment.name));
+            }
+        }
+    }
+    text
+}
+
+fn image_part(dir: &Path, attachment: &Attachment) -> Option<ContentPart> {
+    // only the file name is used so a message can never point outside the folder
+    let file = Path::new(attachment.file.as_ref()?).file_name()?;
+    let data = std::fs::read(dir.join(file)).ok()?;
+    let mime = attachment.mime.as_deref().unwrap_or("application/octet-stream");
+    let url = format!("data:{};base64,{}", mime, base64::engine::general_purpose::STANDARD.encode(data));
+    Some(ContentPart::ImageUrl { image_url: ImageUrl { url } })
+}
+
+// for multimodal backends, images become image parts instead of a note
+pub fn content_parts(message: &UserMessage) -> Vec<ContentPart> {
+    parts_from(&get_attachment_dir(), message)
+}
+
+fn parts_from(dir: &Path, message: &UserMessage) -> Vec<ContentPart> {
+    let text_only = UserMessage {
+        content: message.content.clone(),
+        attachments: message
+            .attachments
+            .iter()
+            .filter(|a| a.kind != AttachmentKind::Image)
+            .cloned()
+            .collect(),
+    };
+    let mut parts = vec![ContentPart::Text { text: prompt_text(&text_only) }];
+    for attachment in message.attachments.iter().filter(|a| a.kind == AttachmentKind::Image) {
+        match image_part(dir, attachment) {
+            Some(part) => parts.push(part),
+            None => eprintln!("attachment {} is missing", attachment.name),
+        }
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment(name: &str, kind: AttachmentKind, content: &str) -> Attachment {
+        Attachment {
+            name: name.to_string(),
+            kind,
+            content: content.to_string(),
+            truncated: false,
+            mime: None,
+            file: None,
+        }
+    }
+
+    #[test]
+    fn truncation_never_cuts_a_character() {
+        assert_eq!(truncate_text("héllo".to_string(), 6), ("héllo".to_string(), false));
+        // "é" takes bytes 1 and 2, a limit of 2 would end inside it
+        assert_eq!(truncate_text("héllo".to_string(), 2), ("h".to_string(), true));
+        assert_eq!(truncate_text("日本".to_string(), 5), ("日".to_string(), true));
+    }
+
+    #[test]
+    fn total_text_is_limited() {
+        let half = "a".repeat(MAX_TOTAL_TEXT_BYTES / 2);
+        let files = vec![
+            attachment("a.txt", AttachmentKind::Text, &half),
+            attachment("b.txt", AttachmentKind::Text, &half),
+        ];
+        assert!(check_total(&files).is_ok());
+        let files = [files, vec![attachment("c.txt", AttachmentKind::Pdf, "x")]].concat();
+        assert_eq!(check_total(&files), Err("attachments have more than 256 KB of text".to_string()));
+    }
+
+    #[test]
+    fn images_become_parts_and_files_stay_in_the_text() {
+        let dir = std::env::temp_dir().join(format!("rasast-attachments-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("cat.png"), b"png").unwrap();
+        let mut image = attachment("photo.png", AttachmentKind::Image, "");
+        image.mime = Some("image/png".to_string());
+        image.file = Some("../cat.png".to_string());
+        let message = UserMessage {
+            content: "look".to_string(),
+            attachments: vec![image, attachment("notes.txt", AttachmentKind::Text, "hi")],
+        };
+        let parts = parts_from(&dir, &message);
+        assert_eq!(
+            serde_json::to_value(&parts).unwrap(),
+            serde_json::json!([
+                {"type": "text", "text": "look\n\n--- file: notes.txt ---\nhi\n--- end of notes.txt ---"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,cG5n"}}
+            ])
+        );
+        // without image parts the model gets a note instead
+        assert!(prompt_text(&message).contains("[image photo.png attached, this model cannot see images]"));
+        std::fs::remove_file(dir.join("cat.png")).unwrap();
+        assert_eq!(parts_from(&dir, &message).len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
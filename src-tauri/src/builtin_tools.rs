@@ -0,0 +1,207 @@
+use std::{collections::HashMap, io::Read as _, path::PathBuf, time::Duration};
+
+use rasast_plugin::{ArgsInfo, Function, PluginManager, SafeValue};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::plugin_sys::{BuiltinFn, PluginCore};
+
+const MAX_FILE_SIZE: u64 = 64 * 1024;
+const MAX_WEB_TEXT: usize = 16 * 1024;
+const MAX_WEB_SIZE: u64 = 2 * 1024 * 1024;
+const WEB_TIMEOUT: Duration = Duration::from_secs(20);
+const MAX_DIR_ENTRIES: usize = 500;
+
+// every built-in tool must be allowed here before the model can see it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BuiltinToolsConfig {
+    #[serde(default = "default_true")]
+    pub datetime: bool,
+    #[serde(default = "default_true")]
+    pub calculator: bool,
+    #[serde(default)]
+    pub read_clipboard: bool,
+    #[serde(default)]
+    pub read_file: bool,
+    #[serde(default)]
+    pub list_directory: bool,
+    #[serde(default)]
+    pub web_fetch: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for BuiltinToolsConfig {
+    fn default() -> Self {
+        Self {
+            datetime: true,
+            calculator: true,
+            read_clipboard: false,
+            read_file: false,
+            list_directory: false,
+            web_fetch: false,
+        }
+    }
+}
+
+fn get_string_arg(args: &HashMap<String, Value>, name: &str) -> Result<String, String> {
+    match args.get(name) {
+        Some(Value::String(s)) => Ok(s.clone()),
+        Some(v) => Ok(v.to_string()),
+        None => Err(format!("missing argument {}", name)),
+    }
+}
+
+fn current_datetime(_args: HashMap<String, Value>) -> Result<SafeValue, String> {
+    let now = chrono::Local::now();
+    Ok(SafeValue::new(serde_json::json!({
+        "datetime": now.to_rfc3339(),
+        "weekday": now.format("%A").to_string(),
+        "timezone": now.format("%:z").to_string(),
+    })))
+}
+
+fn calculate(args: HashMap<String, Value>) -> Result<SafeValue, String> {
+    let expression = get_string_arg(&args, "expression")?;
+    let result = evalexpr::eval(&expression).map_err(|e| e.to_string())?;
+    Ok(SafeValue::new(result.to_string()))
+}
+
+fn read_clipboard(_args: HashMap<String, Value>) -> Result<SafeValue, String> {
+    let mut clipboard = arboard::Clipboard::new().map_err(|e| e.to_string())?;
+    let text = clipboard.get_text().map_err(|e| e.to_string())?;
+    Ok(SafeValue::new(text))
+}
+
+fn read_text_file(args: HashMap<String, Value>) -> Result<SafeValue, String> {
+    let path = PathBuf::from(get_string_arg(&args, "path")?);
+    let file = std::fs::File::open(&path).map_err(|e| e.to_string())?;
+    let size = file.metadata().map_err(|e| e.to_string())?.len();
+    let mut buf = Vec::new();
+    file.take(MAX_FILE_SIZE).read_to_end(&mut buf).map_err(|e| e.to_string())?;
+    let mut text = utf8_prefix(buf, size > MAX_FILE_SIZE)?;
+    if size > MAX_FILE_SIZE {
+        text.push_str("\n[truncated]");
+    }
+    Ok(SafeValue::new(text))
+}
+
+// a cut buffer may end in the middle of a character, only that tail is dropped
+fn utf8_prefix(mut buf: Vec<u8>, truncated: bool) -> Result<String, String> {
+    match std::str::from_utf8(&buf) {
+        Ok(_) => {}
+        Err(e) if truncated && e.error_len().is_none() => buf.truncate(e.valid_up_to()),
+        Err(_) => return Err("file is not valid UTF-8 text".to_string()),
+    }
+    String::from_utf8(buf).map_err(|_| "file is not valid UTF-8 text".to_string())
+}
+
+fn list_directory(args: HashMap<String, Value>) -> Result<SafeValue, String> {
+    let path = PathBuf::from(get_string_arg(&args, "path")?);
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(&path).map_err(|e| e.to_string())?.take(MAX_DIR_ENTRIES) {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+        entries.push(serde_json::json!({
+            "name": entry.file_name().to_string_lossy(),
+            "is_dir": is_dir,
+        }));
+    }
+    Ok(SafeValue::new(entries))
+}
+
+fn web_fetch(args: HashMap<String, Value>) -> Result<SafeValue, String> {
+    let url = get_string_arg(&args, "url")?;
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err("only http and https urls are allowed".to_string());
+    }
+    // tools run on a blocking thread, see get_response_text_async
+    let client = reqwest::blocking::Client::builder()
+        .user_agent("Fopilot/1.0")
+        .timeout(WEB_TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let res = client.get(url).send().map_err(|e| e.to_string())?;
+    let mut html = Vec::new();
+    res.take(MAX_WEB_SIZE).read_to_end(&mut html).map_err(|e| e.to_string())?;
+    let mut text = html2text::from_read(html.as_slice(), 100);
+    if text.len() > MAX_WEB_TEXT {
+        let mut end = MAX_WEB_TEXT;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str("\n[truncated]");
+    }
+    Ok(SafeValue::new(text))
+}
+
+pub fn load_builtin_tools(plugin_core: &mut PluginCore, config: &BuiltinToolsConfig) {
+    let mut manager = PluginManager::new("builtin");
+    let mut callbacks: HashMap<String, BuiltinFn> = HashMap::new();
+    if config.datetime {
+        manager.add_command(Function::new(
+            "get_current_datetime",
+            "Get the current local date, time and timezone",
+            vec![],
+        ));
+        callbacks.insert("get_current_datetime".to_string(), current_datetime);
+    }
+    if config.calculator {
+        manager.add_command(Function::new(
+            "calculate",
+            "Evaluate a math expression such as (2 + 3) * 4 / 7",
+            vec![ArgsInfo::new("string", "expression", "The math expression to evaluate", true)],
+        ));
+        callbacks.insert("calculate".to_string(), calculate);
+    }
+    if config.read_clipboard {
+        manager.add_command(Function::new(
+            "read_clipboard",
+            "Read the text currently in the user's clipboard",
+            vec![],
+        ));
+        callbacks.insert("read_clipboard".to_string(), read_clipboard);
+    }
+    if config.read_file {
+        manager.add_command(Function::new(
+            "read_text_file",
+            "Read the content of a text file on the user's computer",
+            vec![ArgsInfo::new("string", "path", "Absolute path of the file", true)],
+        ));
+        callbacks.insert("read_text_file".to_string(), read_text_file);
+    }
+    if config.list_directory {
+        manager.add_command(Function::new(
+            "list_directory",
+            "List the files and folders inside a directory on the user's computer",
+            vec![ArgsInfo::new("string", "path", "Absolute path of the directory", true)],
+        ));
+        callbacks.insert("list_directory".to_string(), list_directory);
+    }
+    if config.web_fetch {
+        manager.add_command(Function::new(
+            "web_fetch",
+            "Download a web page and return its content as plain text",
+            vec![ArgsInfo::new("string", "url", "The http or https url to fetch", true)],
+        ));
+        callbacks.insert("web_fetch".to_string(), web_fetch);
+    }
+    plugin_core.add_builtin(manager, callbacks);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_prefix_drops_cut_character() {
+        let mut buf = "héllo".as_bytes().to_vec();
+        buf.truncate(2);
+        assert_eq!(utf8_prefix(buf.clone(), true).unwrap(), "h");
+        assert!(utf8_prefix(buf, false).is_err());
+        assert!(utf8_prefix(vec![b'a', 0xff, b'b'], true).is_err());
+    }
+}
@@ -0,0 +1,350 @@
+use std::{
+    io::{BufRead as _, BufReader, Write as _},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{mpsc::channel, Arc},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::{async_runtime::Mutex, Manager as _, State};
+
+use crate::{
+    attachment::{self, Attachment},
+    commands::generate_reply,
+    conversation::Conversation,
+    hotkey::new_chat,
+    profile::RouteTask,
+    quick_action,
+    tokenizer::{MessageType, UserMessage},
+    utility::generate_random_string,
+};
+
+pub const USAGE: &str = "usage:
+  rasast                              start the app, or show the running one
+  rasast ask <question> [--file PATH]  ask the running assistant and print the answer
+  rasast --new-chat                   start a new chat
+  rasast --file PATH                  attach a file to the next message
+  rasast --quick-action ID [--file PATH]
+                                      run a quick action on the clipboard, or the file, and print the result
+  rasast --portable                   keep config and data next to the executable
+  rasast --headless                   run without window, tray or hotkeys and serve the local api";
+
+// an answer can take a while on a slow backend, but a crashed app should not hang a script forever
+const REPLY_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplyTo {
+    pub addr: SocketAddr,
+    pub token: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CliRequest {
+    pub ask: Option<String>,
+    pub new_chat: bool,
+    pub files: Vec<PathBuf>,
+    pub quick_action: Option<String>,
+    // added by the printing process, the running app sends the answer there
+    pub reply_to: Option<ReplyTo>,
+}
+
+impl CliRequest {
+    pub fn prints_answer(&self) -> bool {
+        self.ask.is_some() || self.quick_action.is_some()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cli {
+    // no command, e.g. autostart, a deep link or a plain start
+    App,
+    Help,
+    Request(CliRequest),
+}
+
+// args without the program name, the same list comes back from the single instance plugin
+pub fn parse(args: &[String]) -> Result<Cli, String> {
+    let mut request = CliRequest::default();
+    let mut reply_addr = None;
+    let mut reply_token = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().cloned().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "-h" | "--help" | "help" => return Ok(Cli::Help),
+            "--portable" | "--headless" => {}
+            "--new-chat" => request.new_chat = true,
+            "--file" => request.files.push(PathBuf::from(value("--file")?)),
+            "--quick-action" => request.quick_action = Some(value("--quick-action")?),
+            "--reply-to" => {
+                let addr = value("--reply-to")?;
+                reply_addr = Some(addr.parse::<SocketAddr>().map_err(|_| format!("{} is not an address", addr))?);
+            }
+            "--reply-token" => reply_token = Some(value("--reply-token")?),
+            "ask" => {
+                // everything after ask that is not a flag is the question
+                let mut words = Vec::new();
+                while let Some(word) = args.next() {
+                    match word.as_str() {
+                        "--new-chat" => request.new_chat = true,
+                        "--file" => request.files.push(PathBuf::from(
+                            args.next().ok_or("--file needs a value")?,
+                        )),
+                        "--reply-to" | "--reply-token" | "--quick-action" | "--portable" | "--headless" => {
+                            return Err(format!("{} goes before ask", word))
+                        }
+                        _ => words.push(word.as_str()),
+                    }
+                }
+                let question = words.join(" ").trim().to_string();
+                if question.is_empty() {
+                    return Err("ask needs a question".to_string());
+                }
+                request.ask = Some(question);
+            }
+            link if link.starts_with(&format!("{}://", crate::deep_link::SCHEME)) => {}
+            // macos adds a process serial number when the app is started from finder
+            psn if psn.starts_with("-psn_") => {}
+            other => return Err(format!("unknown argument {}, see rasast --help", other)),
+        }
+    }
+    if request.ask.is_some() && request.quick_action.is_some() {
+        return Err("ask and --quick-action cannot be used together".to_string());
+    }
+    request.reply_to = match (reply_addr, reply_token) {
+        (Some(addr), Some(token)) => Some(ReplyTo { addr, token }),
+        (None, None) => None,
+        _ => return Err("--reply-to and --reply-token go together".to_string()),
+    };
+    if request == CliRequest::default() {
+        return Ok(Cli::App);
+    }
+    Ok(Cli::Request(request))
+}
+
+// only the first start counts, a second one is handed to the running app
+pub fn is_headless() -> bool {
+    std::env::args().any(|arg| arg == "--headless")
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct CliReply {
+    token: String,
+    ok: bool,
+    text: String,
+}
+
+// a release build on windows has no console until it attaches to the one it was started from
+#[cfg(windows)]
+pub fn attach_console() {
+    use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(windows))]
+pub fn attach_console() {}
+
+fn wait_for_reply(listener: TcpListener, token: String) -> Result<CliReply, String> {
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let mut line = String::new();
+            if BufReader::new(stream).read_line(&mut line).is_err() {
+                continue;
+            }
+            // anything else on this computer can connect, only the app knows the token
+            match serde_json::from_str::<CliReply>(&line) {
+                Ok(reply) if reply.token == token => {
+                    let _ = sender.send(reply);
+                    return;
+                }
+                _ => continue,
+            }
+        }
+    });
+    receiver
+        .recv_timeout(REPLY_TIMEOUT)
+        .map_err(|_| "no answer from the app".to_string())
+}
+
+// starts the app again with a reply address, the single instance plugin hands those args to the
+// running app (or the new process becomes the app), then the answer is printed here
+pub fn run_client(args: &[String]) -> i32 {
+    attach_console();
+    let listener = match TcpListener::bind("127.0.0.1:0") {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("cannot wait for the answer: {}", e);
+            return 1;
+        }
+    };
+    let addr = listener.local_addr().unwrap();
+    let token = generate_random_string(32);
+    let child = std::env::current_exe().and_then(|exe| {
+        // before the command, everything after ask is the question
+        Command::new(exe)
+            .arg("--reply-to")
+            .arg(addr.to_string())
+            .arg("--reply-token")
+            .arg(&token)
+            .args(args)
+            // the app may keep running from this process, its logs are not the answer
+            .stdout(Stdio::null())
+            .spawn()
+    });
+    if let Err(e) = child {
+        eprintln!("cannot start the app: {}", e);
+        return 1;
+    }
+    match wait_for_reply(listener, token) {
+        Ok(reply) if reply.ok => {
+            println!("{}", reply.text);
+            0
+        }
+        Ok(reply) => {
+            eprintln!("{}", reply.text);
+            1
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+fn send_reply(reply_to: &ReplyTo, result: Result<String, String>) -> std::io::Result<()> {
+    let (ok, text) = match result {
+        Ok(text) => (true, text),
+        Err(text) => (false, text),
+    };
+    let reply = CliReply { token: reply_to.token.clone(), ok, text };
+    let mut stream = TcpStream::connect_timeout(&reply_to.addr, Duration::from_secs(5))?;
+    writeln!(stream, "{}", serde_json::to_string(&reply).unwrap())
+}
+
+async fn load_files(files: Vec<PathBuf>) -> Result<Vec<Attachment>, String> {
+    let attachments = tauri::async_runtime::spawn_blocking(move || {
+        files
+            .iter()
+            .map(|path| attachment::load_attachment(path))
+            .collect::<Result<Vec<Attachment>, String>>()
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+    attachment::check_total(&attachments)?;
+    Ok(attachments)
+}
+
+async fn run(app: &tauri::AppHandle, request: &CliRequest, cwd: &Path) -> Result<String, String> {
+    if request.new_chat {
+        new_chat(app).await;
+    }
+    // paths are relative to where the command was typed, not to the running app
+    let files: Vec<PathBuf> = request.files.iter().map(|file| cwd.join(file)).collect();
+    if let Some(id) = &request.quick_action {
+        let input = match files.as_slice() {
+            [] => None,
+            [file] => Some(std::fs::read_to_string(file).map_err(|e| format!("cannot read {}: {}", file.display(), e))?),
+            _ => return Err("a quick action takes one --file".to_string()),
+        };
+        return quick_action::run_quick_action(app.clone(), id.clone(), input).await;
+    }
+    let attachments = load_files(files).await?;
+    let Some(prompt) = &request.ask else {
+        crate::create_main_window(app.clone());
+        if !attachments.is_empty() {
+            let _ = app.emit_all("attach-files", attachments);
+        }
+        return Ok(String::new());
+    };
+    let conversation: State<Arc<Mutex<Conversation>>> = app.state();
+    conversation
+        .lock()
+        .await
+        .push(MessageType::User(UserMessage { content: prompt.clone(), attachments }));
+    let _ = app.emit_all("conversation-changed", ());
+    let answer = generate_reply(app.clone(), uuid::Uuid::new_v4().to_string(), RouteTask::Chat).await;
+    let _ = app.emit_all("conversation-changed", ());
+    answer.map(|text| text.trim().to_string())
+}
+
+pub async fn handle(app: &tauri::AppHandle, request: CliRequest, cwd: PathBuf) {
+    let result = run(app, &request, &cwd).await;
+    if let Err(e) = &result {
+        eprintln!("cli error {}", e);
+    }
+    if let Some(reply_to) = request.reply_to {
+        let sent = tauri::async_runtime::spawn_blocking(move || send_reply(&reply_to, result)).await;
+        if let Ok(Err(e)) = sent {
+            eprintln!("send cli answer error {}", e);
+        }
+    }
+}
+
+// the args of a second start, or of this process when it became the app
+pub fn spawn_handle(app: &tauri::AppHandle, args: &[String], cwd: PathBuf) {
+    match parse(args) {
+        Ok(Cli::Request(request)) => {
+            let app = app.clone();
+            tauri::async_runtime::spawn(async move { handle(&app, request, cwd).await });
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("cli error {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse(&[]).unwrap(), Cli::App);
+        assert_eq!(parse(&args(&["--portable", "--headless", "aihelper://settings"])).unwrap(), Cli::App);
+        assert_eq!(parse(&args(&["--help"])).unwrap(), Cli::Help);
+        let Cli::Request(request) = parse(&args(&["--new-chat", "ask", "what", "is", "rust", "--file", "a.txt"])).unwrap() else {
+            panic!()
+        };
+        assert_eq!(request.ask.as_deref(), Some("what is rust"));
+        assert!(request.new_chat);
+        assert_eq!(request.files, vec![PathBuf::from("a.txt")]);
+        assert!(request.prints_answer());
+        let Cli::Request(request) = parse(&args(&["--quick-action", "summarize", "--reply-to", "127.0.0.1:4000", "--reply-token", "t"])).unwrap() else {
+            panic!()
+        };
+        assert_eq!(request.quick_action.as_deref(), Some("summarize"));
+        assert_eq!(request.reply_to.unwrap().addr.port(), 4000);
+        let Cli::Request(request) = parse(&args(&["--file", "notes.md"])).unwrap() else { panic!() };
+        assert!(!request.prints_answer());
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        assert!(parse(&args(&["ask"])).is_err());
+        assert!(parse(&args(&["--file"])).is_err());
+        assert!(parse(&args(&["--frobnicate"])).is_err());
+        assert!(parse(&args(&["--quick-action", "x", "ask", "hi"])).is_err());
+        assert!(parse(&args(&["--reply-to", "127.0.0.1:1", "ask", "hi"])).is_err());
+        assert!(parse(&args(&["--reply-to", "nowhere", "--reply-token", "t"])).is_err());
+    }
+
+    #[test]
+    fn reply_needs_the_token() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let waiting = std::thread::spawn(move || wait_for_reply(listener, "secret".to_string()));
+        send_reply(&ReplyTo { addr, token: "guess".to_string() }, Ok("fake".to_string())).unwrap();
+        send_reply(&ReplyTo { addr, token: "secret".to_string() }, Ok("4".to_string())).unwrap();
+        let reply = waiting.join().unwrap().unwrap();
+        assert!(reply.ok);
+        assert_eq!(reply.text, "4");
+    }
+}
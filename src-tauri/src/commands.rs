@@ -1,8 +1,26 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 use serde::{Deserialize, Serialize};
 use tauri::{async_runtime::Mutex, Manager, State};
 use crate::tokenizer::*;
 use crate::api_req::get_response_text;
+use crate::attachment::{self, Attachment};
+use crate::config::{self, ConfigErrors, ConfigUpdate};
+use crate::paths::{self, data_dir, DataPaths};
+use crate::conversation::{get_conversation_dir, save_current, Conversation, MessageNode};
+use crate::documents::{self, DocumentsConfig, IndexStats};
+use crate::embedding::Embedder;
+use crate::export::{self, ExportFormat};
+use crate::generation::GenerationParams;
+use crate::hotkey::{self, HotkeyAction, HotkeyError, Keymap};
+use crate::import;
+use crate::persona::{self, Persona};
+use crate::profile::{self, select_profile, ModelProfile, RouteTask, RoutingRule};
+use crate::quick_action::{self, QuickAction};
+use crate::search::{self, SearchHit};
+use crate::secrets::{SecretStore, SecretsStatus};
+use crate::speech::{SpeechConfig, Speaker};
+use crate::voice::{self, VoiceConfig, VoiceInput};
+use crate::serde_obj::{ConfigFile, ToolCallFn, ToolEventPayload, ToolStatus};
 
 #[tauri::command]
 pub fn md_to_html(text: String) -> Result<String, String> {
@@ -14,38 +32,713 @@ pub fn generate_uuid() -> uuid::Uuid {
     uuid::Uuid::new_v4()
 }
 
+// the chat gets the answer and any error through the message events, the result is for
+// callers outside the window that need this reply and not whatever the open chat ends with
+pub async fn generate_reply(app: tauri::AppHandle, id: String, task: RouteTask) -> Result<String, String> {
+    let profile = select_profile(&app, task).await;
+    let conversation_mutex: State<Arc<Mutex<Conversation>>> = app.state();
+    let conversation = conversation_mutex.lock().await;
+    let persona = conversation.persona.clone();
+    let messeges = conversation.prompt_messages();
+    drop(conversation);
+    let prompt = build_prompt(&app, profile, persona, messeges).await;
+    get_response_text(prompt, app, id).await
+}
+
+#[tauri::command(async)]
+pub async fn new_message(
+    app: tauri::AppHandle,
+    prompt: String,
+    attachments: Option<Vec<Attachment>>,
+    id: String,
+) -> Result<(), String> {
+    let attachments = attachments.unwrap_or_default();
+    attachment::check_total(&attachments)?;
+    let app_binding = app.clone();
+    let conversation_mutex: State<Arc<Mutex<Conversation>>> = app_binding.state();
+    conversation_mutex.lock().await.push(MessageType::User(UserMessage { content: prompt, attachments }));
+    let _ = generate_reply(app, id, RouteTask::Chat).await;
+    Ok(())
+}
+
+// the edited message becomes a sibling of the original so the old branch stays reachable
 #[tauri::command(async)]
-pub async fn new_message(app: tauri::AppHandle, prompt: String, id: String) -> Result<(), String> {
+pub async fn edit_message(app: tauri::AppHandle, node: usize, content: String, id: String) -> Result<(), String> {
     let app_binding = app.clone();
-    let messages_mutex: State<Arc<Mutex<Vec<MessageType>>>> = app_binding.state();
-    messages_mutex.lock().await.push(MessageType::User(UserMessage { content: prompt }));
-    let messeges = messages_mutex.lock().await.clone();
-    let text = tokenize_messages(messeges, app.clone());
-    get_response_text(text, app, id).await;
+    let conversation_mutex: State<Arc<Mutex<Conversation>>> = app_binding.state();
+    let mut conversation = conversation_mutex.lock().await;
+    // the files stay attached to the edited message
+    let (parent, attachments) = match conversation.get(node) {
+        Some(MessageNode { message: MessageType::User(m), parent, .. }) => (*parent, m.attachments.clone()),
+        Some(_) => return Err("only user messages can be edited".to_string()),
+        None => return Err(format!("message {} not found", node)),
+    };
+    conversation.add_child(parent, MessageType::User(UserMessage { content, attachments }));
+    drop(conversation);
+    let _ = generate_reply(app, id, RouteTask::Chat).await;
+    Ok(())
+}
+
+// writes a new reply next to the old one, `node` can be any message of the reply
+#[tauri::command(async)]
+pub async fn regenerate_message(app: tauri::AppHandle, node: usize, id: String) -> Result<(), String> {
+    let app_binding = app.clone();
+    let conversation_mutex: State<Arc<Mutex<Conversation>>> = app_binding.state();
+    let mut conversation = conversation_mutex.lock().await;
+    if conversation.get(node).is_none() {
+        return Err(format!("message {} not found", node));
+    }
+    let mut current = Some(node);
+    while let Some(idx) = current {
+        if let MessageType::User(_) = conversation.nodes[idx].message {
+            break;
+        }
+        current = conversation.nodes[idx].parent;
+    }
+    let Some(user_node) = current else {
+        return Err("no user message to answer".to_string());
+    };
+    conversation.truncate_after(user_node);
+    drop(conversation);
+    let _ = generate_reply(app, id, RouteTask::Chat).await;
+    Ok(())
+}
+
+#[tauri::command(async)]
+pub async fn switch_branch(app: tauri::AppHandle, node: usize, offset: i32) -> Result<(), String> {
+    let conversation_mutex: State<Arc<Mutex<Conversation>>> = app.state();
+    let mut conversation = conversation_mutex.lock().await;
+    if conversation.get(node).is_none() {
+        return Err(format!("message {} not found", node));
+    }
+    let siblings = conversation.siblings(node);
+    let pos = siblings.iter().position(|idx| *idx == node).unwrap() as i32;
+    let target = (pos + offset).clamp(0, siblings.len() as i32 - 1) as usize;
+    conversation.select(siblings[target]);
+    drop(conversation);
+    save_current(&app).await;
     Ok(())
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Message {
     is_user: bool,
-    content: String
+    content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool: Option<ToolEventPayload>,
+    #[serde(default)]
+    is_summary: bool,
+    // node id in the conversation tree, used to edit, regenerate and switch branches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    node: Option<usize>,
+    #[serde(default)]
+    branch_index: usize,
+    #[serde(default)]
+    branch_count: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+}
+
+impl Message {
+    fn new(is_user: bool, content: String) -> Self {
+        Self {
+            is_user,
+            content,
+            tool: None,
+            is_summary: false,
+            node: None,
+            branch_index: 0,
+            branch_count: 1,
+            attachments: Vec::new(),
+            model: None,
+        }
+    }
+
+    fn with_node(mut self, conversation: &Conversation, node: usize) -> Self {
+        let siblings = conversation.siblings(node);
+        self.node = Some(node);
+        self.branch_index = siblings.iter().position(|idx| *idx == node).unwrap_or(0);
+        self.branch_count = siblings.len();
+        self
+    }
 }
 
 #[tauri::command(async)]
-pub async fn get_messages(messages: State<'_, Arc<Mutex<Vec<MessageType>>>>) -> Result<Vec<Message>, String> {
-    let message = messages.lock().await.clone();
+pub async fn get_messages(conversation: State<'_, Arc<Mutex<Conversation>>>) -> Result<Vec<Message>, String> {
+    let conversation = conversation.lock().await.clone();
+    Ok(transcript(&conversation))
+}
+
+// responses without an id, e.g. in chats saved before ids were made, answer the calls of the
+// latest round in the order they were made
+fn answer_tool(messages: &mut [Message], response: &ToolResponse) {
+    let round = messages.iter().rev().find(|m| m.tool.is_some()).and_then(|m| m.node);
+    let by_id = response
+        .call_id
+        .as_ref()
+        .and_then(|id| messages.iter().rposition(|m| m.tool.as_ref().is_some_and(|t| &t.call_id == id)));
+    let idx = by_id.or_else(|| {
+        messages
+            .iter()
+            .position(|m| m.node == round && m.tool.as_ref().is_some_and(|t| t.result.is_none()))
+    });
+    let Some(tool) = idx.and_then(|idx| messages[idx].tool.as_mut()) else {
+        return;
+    };
+    tool.status = response.status;
+    tool.result = Some(response.content.clone());
+    tool.duration_ms = Some(response.duration_ms);
+}
+
+fn transcript(conversation: &Conversation) -> Vec<Message> {
+    let summary = conversation.active_summary();
     let mut j_message = Vec::new();
-    for message in message.iter() {
-        match message {     
+    for (idx, node) in conversation.active_path().into_iter().enumerate() {
+        if let Some(summary) = summary.filter(|s| s.covered == idx) {
+            let mut note = Message::new(false, summary.content.clone());
+            note.is_summary = true;
+            j_message.push(note);
+        }
+        match &conversation.nodes[node].message {     
             MessageType::User(user_message) => { 
-                j_message.push(Message { is_user: true, content: user_message.content.clone() }); 
+                let mut entry = Message::new(true, user_message.content.clone()).with_node(conversation, node);
+                entry.attachments = user_message.attachments.iter().map(|a| a.name.clone()).collect();
+                j_message.push(entry); 
             },
             MessageType::Assistant(assistant_message) => { 
-                j_message.push(Message { is_user: false, content: assistant_message.content.clone() }); 
+                let mut entry = Message::new(false, assistant_message.content.clone()).with_node(conversation, node);
+                entry.model = assistant_message.model.clone();
+                j_message.push(entry); 
+            },
+            MessageType::ToolCall(tool_call) => {
+                let tool_calls: Vec<ToolCallFn> = serde_json::from_str(&tool_call.content).unwrap_or_default();
+                for tool_call in tool_calls {
+                    let tool = ToolEventPayload {
+                        uuid: String::new(),
+                        call_id: tool_call.call_id.unwrap_or_default(),
+                        name: tool_call.name,
+                        arguments: tool_call.arguments,
+                        status: ToolStatus::Running,
+                        result: None,
+                        duration_ms: None,
+                    };
+                    let mut entry = Message::new(false, String::new()).with_node(conversation, node);
+                    entry.tool = Some(tool);
+                    j_message.push(entry);
+                }
             },
+            MessageType::ToolResponse(tool_response) => answer_tool(&mut j_message, tool_response),
             _ => {}
         }
     }
-    Ok(j_message)
+    j_message
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PersonaList {
+    personas: Vec<Persona>,
+    default_persona: String,
+    current: Option<String>,
+}
+
+#[tauri::command(async)]
+pub async fn get_personas(
+    config: State<'_, Arc<Mutex<ConfigFile>>>,
+    conversation: State<'_, Arc<Mutex<Conversation>>>,
+) -> Result<PersonaList, String> {
+    let config = config.lock().await;
+    Ok(PersonaList {
+        personas: config.personas.clone(),
+        default_persona: config.default_persona.clone(),
+        current: conversation.lock().await.persona.clone(),
+    })
+}
+
+#[tauri::command(async)]
+pub async fn save_personas(
+    app: tauri::AppHandle,
+    personas: Vec<Persona>,
+    default_persona: String,
+) -> Result<(), String> {
+    persona::validate(&personas, &default_persona)?;
+    let config_s: State<Arc<Mutex<ConfigFile>>> = app.state();
+    let mut config = config_s.lock().await;
+    config.personas = personas;
+    config.default_persona = default_persona;
+    config::save(&app, &config)?;
+    Ok(())
+}
+
+#[tauri::command(async)]
+pub async fn set_conversation_persona(
+    conversation: State<'_, Arc<Mutex<Conversation>>>,
+    config: State<'_, Arc<Mutex<ConfigFile>>>,
+    name: Option<String>,
+) -> Result<(), String> {
+    if let Some(name) = &name {
+        if !config.lock().await.personas.iter().any(|p| &p.name == name) {
+            return Err(format!("persona {} not found", name));
+        }
+    }
+    conversation.lock().await.persona = name;
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProfileList {
+    profiles: Vec<ModelProfile>,
+    default_profile: String,
+    routing: Vec<RoutingRule>,
+    current: Option<String>,
 }
 
+#[tauri::command(async)]
+pub async fn get_profiles(
+    config: State<'_, Arc<Mutex<ConfigFile>>>,
+    conversation: State<'_, Arc<Mutex<Conversation>>>,
+) -> Result<ProfileList, String> {
+    let config = config.lock().await;
+    Ok(ProfileList {
+        profiles: config.profiles.clone(),
+        default_profile: config.default_profile.clone(),
+        routing: config.routing.clone(),
+        current: conversation.lock().await.profile.clone(),
+    })
+}
+
+#[tauri::command(async)]
+pub async fn save_profiles(
+    app: tauri::AppHandle,
+    profiles: Vec<ModelProfile>,
+    default_profile: String,
+    routing: Vec<RoutingRule>,
+) -> Result<(), String> {
+    profile::validate(&profiles, &default_profile, &routing)?;
+    let config_s: State<Arc<Mutex<ConfigFile>>> = app.state();
+    let mut config = config_s.lock().await;
+    config.profiles = profiles;
+    config.default_profile = default_profile;
+    config.routing = routing;
+    config::save(&app, &config)?;
+    Ok(())
+}
+
+#[tauri::command(async)]
+pub async fn set_conversation_profile(
+    conversation: State<'_, Arc<Mutex<Conversation>>>,
+    config: State<'_, Arc<Mutex<ConfigFile>>>,
+    name: Option<String>,
+) -> Result<(), String> {
+    if let Some(name) = &name {
+        if !config.lock().await.profiles.iter().any(|p| &p.name == name) {
+            return Err(format!("profile {} not found", name));
+        }
+    }
+    conversation.lock().await.profile = name;
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GenerationParamsInfo {
+    default: GenerationParams,
+    conversation: Option<GenerationParams>,
+}
+
+#[tauri::command(async)]
+pub async fn get_generation_params(
+    config: State<'_, Arc<Mutex<ConfigFile>>>,
+    conversation: State<'_, Arc<Mutex<Conversation>>>,
+) -> Result<GenerationParamsInfo, String> {
+    Ok(GenerationParamsInfo {
+        default: config.lock().await.generation.clone(),
+        conversation: conversation.lock().await.params.clone(),
+    })
+}
+
+#[tauri::command(async)]
+pub async fn save_generation_params(app: tauri::AppHandle, params: GenerationParams) -> Result<(), String> {
+    params.validate()?;
+    let config_s: State<Arc<Mutex<ConfigFile>>> = app.state();
+    let mut config = config_s.lock().await;
+    config.generation = params;
+    config::save(&app, &config)?;
+    Ok(())
+}
+
+#[tauri::command(async)]
+pub async fn set_conversation_params(
+    conversation: State<'_, Arc<Mutex<Conversation>>>,
+    params: Option<GenerationParams>,
+) -> Result<(), String> {
+    if let Some(params) = &params {
+        params.validate()?;
+    }
+    conversation.lock().await.params = params;
+    Ok(())
+}
+
+// writes the current conversation to `path`, or to the exports folder, and returns where it went
+#[tauri::command(async)]
+pub async fn export_conversation(
+    config: State<'_, Arc<Mutex<ConfigFile>>>,
+    conversation: State<'_, Arc<Mutex<Conversation>>>,
+    format: ExportFormat,
+    path: Option<String>,
+) -> Result<String, String> {
+    let conversation = conversation.lock().await.clone();
+    if conversation.is_empty() {
+        return Err("nothing to export".to_string());
+    }
+    // older replies have no model recorded, they are credited to the conversation's chat profile
+    let model = profile::resolve(&*config.lock().await, conversation.profile.as_deref(), RouteTask::Chat).model;
+    let path = export::export_conversation(&conversation, &model, format, path.map(PathBuf::from))?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConversationInfo {
+    id: String,
+    title: String,
+    created_at: i64,
+    updated_at: i64,
+}
+
+impl ConversationInfo {
+    fn new(conversation: &Conversation) -> Self {
+        Self {
+            id: conversation.id.clone(),
+            title: export::conversation_title(conversation),
+            created_at: conversation.created_at,
+            updated_at: conversation.updated_at,
+        }
+    }
+}
+
+// imported chats are written straight to the history folder
+#[tauri::command(async)]
+pub async fn import_conversations(
+    config: State<'_, Arc<Mutex<ConfigFile>>>,
+    path: String,
+) -> Result<Vec<ConversationInfo>, String> {
+    // imported chats only live on disk, writing them would go around the setting
+    if !config.lock().await.save_on_close {
+        return Err("turn on \"Keep the chat when the window closes\" to import conversations".to_string());
+    }
+    let data = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    let conversations = import::parse_import(&data)?;
+    let mut imported = Vec::new();
+    for conversation in conversations {
+        conversation.write()?;
+        imported.push(ConversationInfo::new(&conversation));
+    }
+    Ok(imported)
+}
+
+// the current chat is searched too even when it has not been written to disk
+#[tauri::command(async)]
+pub async fn search_messages(
+    app: tauri::AppHandle,
+    query: String,
+    semantic: Option<bool>,
+    limit: Option<usize>,
+) -> Result<Vec<SearchHit>, String> {
+    let conversation_mutex: State<Arc<Mutex<Conversation>>> = app.state();
+    let current = conversation_mutex.lock().await.clone();
+    let embedder = if semantic.unwrap_or(false) {
+        let embedder: State<Option<Arc<dyn Embedder>>> = app.state();
+        let embedder = embedder.inner().clone();
+        Some(embedder.ok_or("semantic search needs embedding.model_path in the config")?)
+    } else {
+        None
+    };
+    let limit = limit.unwrap_or(20);
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut conn = search::open_index(&data_dir().join("search.db"))?;
+        search::sync(&mut conn, &get_conversation_dir(), &current)?;
+        match embedder {
+            Some(embedder) => {
+                search::sync_embeddings(&mut conn, embedder.as_ref())?;
+                search::search_semantic(&conn, embedder.as_ref(), &query, limit)
+            }
+            None => search::search_text(&conn, &query, limit),
+        }
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// jump to a search hit, `node` becomes part of the active branch
+#[tauri::command(async)]
+pub async fn open_conversation(app: tauri::AppHandle, id: String, node: Option<usize>) -> Result<(), String> {
+    let conversation_mutex: State<Arc<Mutex<Conversation>>> = app.state();
+    if conversation_mutex.lock().await.id != id {
+        let loaded = Conversation::load(&id).ok_or(format!("conversation {} not found", id))?;
+        save_current(&app).await;
+        *conversation_mutex.lock().await = loaded;
+    }
+    let mut conversation = conversation_mutex.lock().await;
+    if let Some(node) = node {
+        if conversation.get(node).is_none() {
+            return Err(format!("message {} not found", node));
+        }
+        conversation.select(node);
+    }
+    Ok(())
+}
+
+#[tauri::command(async)]
+pub async fn get_documents_config(config: State<'_, Arc<Mutex<ConfigFile>>>) -> Result<DocumentsConfig, String> {
+    Ok(config.lock().await.documents.clone())
+}
+
+#[tauri::command(async)]
+pub async fn save_documents_config(app: tauri::AppHandle, documents: DocumentsConfig) -> Result<(), String> {
+    documents.validate()?;
+    let config_s: State<Arc<Mutex<ConfigFile>>> = app.state();
+    let mut config = config_s.lock().await;
+    config.documents = documents;
+    config::save(&app, &config)?;
+    Ok(())
+}
+
+#[tauri::command(async)]
+pub async fn reindex_documents(app: tauri::AppHandle) -> Result<IndexStats, String> {
+    documents::reindex(&app).await
+}
+
+// paths come from the file drop event, every file is read once here and kept in the message
+#[tauri::command(async)]
+pub async fn attach_files(paths: Vec<String>) -> Result<Vec<Attachment>, String> {
+    let attachments = tauri::async_runtime::spawn_blocking(move || {
+        paths
+            .iter()
+            .map(|path| attachment::load_attachment(std::path::Path::new(path)))
+            .collect::<Result<Vec<Attachment>, String>>()
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+    attachment::check_total(&attachments)?;
+    Ok(attachments)
+}
+
+#[tauri::command(async)]
+pub async fn get_quick_actions(config: State<'_, Arc<Mutex<ConfigFile>>>) -> Result<Vec<QuickAction>, String> {
+    Ok(config.lock().await.quick_actions.clone())
+}
+
+#[tauri::command(async)]
+pub async fn save_quick_actions(app: tauri::AppHandle, actions: Vec<QuickAction>) -> Result<(), String> {
+    quick_action::validate(&actions)?;
+    let config_s: State<Arc<Mutex<ConfigFile>>> = app.state();
+    let mut config = config_s.lock().await;
+    config.quick_actions = actions;
+    // hotkeys of removed actions go away with them
+    let available = hotkey::available_actions(&config.quick_actions);
+    config.hotkeys.retain(|action, _| available.iter().any(|a| &a.id == action));
+    config::save(&app, &config)?;
+    let tray_menu = crate::create_tray_menu(&config);
+    let keymap = config.hotkeys.clone();
+    // the tray and the shortcut manager wait for the main thread, which may be waiting for the config
+    drop(config);
+    if let Err(e) = app.tray_handle().set_menu(tray_menu) {
+        eprintln!("update tray menu error {}", e);
+    }
+    for error in hotkey::apply(&app, &keymap) {
+        eprintln!("register hotkey {} for {} error {}", error.shortcut, error.action, error.error);
+    }
+    Ok(())
+}
+
+#[tauri::command(async)]
+pub async fn run_quick_action(app: tauri::AppHandle, id: String) -> Result<(), String> {
+    quick_action::run_quick_action(app, id, None).await.map(|_| ())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HotkeyInfo {
+    keymap: Keymap,
+    actions: Vec<HotkeyAction>,
+}
+
+#[tauri::command(async)]
+pub async fn get_hotkeys(config: State<'_, Arc<Mutex<ConfigFile>>>) -> Result<HotkeyInfo, String> {
+    let config = config.lock().await;
+    Ok(HotkeyInfo {
+        keymap: config.hotkeys.clone(),
+        actions: hotkey::available_actions(&config.quick_actions),
+    })
+}
+
+// saved even when some keys are taken by another program, those come back so the ui can show them
+#[tauri::command(async)]
+pub async fn save_hotkeys(app: tauri::AppHandle, keymap: Keymap) -> Result<Vec<HotkeyError>, String> {
+    let keymap: Keymap = keymap.into_iter().filter(|(_, s)| !s.trim().is_empty()).collect();
+    let config_s: State<Arc<Mutex<ConfigFile>>> = app.state();
+    let mut config = config_s.lock().await;
+    hotkey::validate(&keymap, &config.quick_actions)?;
+    config.hotkeys = keymap.clone();
+    config::save(&app, &config)?;
+    // the shortcut manager waits for the main thread, which may be waiting for the config
+    drop(config);
+    Ok(hotkey::apply(&app, &keymap))
+}
+
+#[tauri::command(async)]
+pub async fn new_conversation(app: tauri::AppHandle) -> Result<(), String> {
+    hotkey::new_chat(&app).await;
+    Ok(())
+}
+
+#[tauri::command(async)]
+pub async fn get_speech_config(config: State<'_, Arc<Mutex<ConfigFile>>>) -> Result<SpeechConfig, String> {
+    Ok(config.lock().await.speech.clone())
+}
+
+#[tauri::command(async)]
+pub async fn save_speech_config(app: tauri::AppHandle, speech: SpeechConfig) -> Result<(), String> {
+    let config_s: State<Arc<Mutex<ConfigFile>>> = app.state();
+    let mut config = config_s.lock().await;
+    config.speech = speech;
+    config::save(&app, &config)?;
+    let speaker: State<Speaker> = app.state();
+    speaker.set_config(&config.speech)
+}
+
+#[tauri::command(async)]
+pub async fn speak_text(speaker: State<'_, Speaker>, text: String) -> Result<(), String> {
+    speaker.say(&text)
+}
+
+#[tauri::command]
+pub fn stop_speaking(speaker: State<'_, Speaker>) {
+    speaker.stop();
+}
+
+#[tauri::command(async)]
+pub async fn get_voice_config(config: State<'_, Arc<Mutex<ConfigFile>>>) -> Result<VoiceConfig, String> {
+    Ok(config.lock().await.voice.clone())
+}
+
+#[tauri::command(async)]
+pub async fn save_voice_config(app: tauri::AppHandle, voice: VoiceConfig) -> Result<(), String> {
+    voice.validate()?;
+    let config_s: State<Arc<Mutex<ConfigFile>>> = app.state();
+    let mut config = config_s.lock().await;
+    config.voice = voice;
+    config::save(&app, &config)?;
+    let voice_input: State<VoiceInput> = app.state();
+    voice_input.reset();
+    Ok(())
+}
+
+#[tauri::command(async)]
+pub async fn start_voice_input(app: tauri::AppHandle) -> Result<(), String> {
+    voice::start(&app).await
+}
+
+// the transcript comes back as a voice-input event
+#[tauri::command(async)]
+pub async fn stop_voice_input(app: tauri::AppHandle) -> Result<(), String> {
+    voice::finish(&app).await;
+    Ok(())
+}
+
+// only names and the lock state ever go to the ui, values stay in the store
+#[tauri::command(async)]
+pub fn get_secrets_status(store: State<'_, SecretStore>) -> SecretsStatus {
+    store.status()
+}
+
+#[tauri::command(async)]
+pub fn unlock_secrets(store: State<'_, SecretStore>, passphrase: String) -> Result<SecretsStatus, String> {
+    store.unlock(&passphrase)?;
+    Ok(store.status())
+}
+
+#[tauri::command(async)]
+pub fn set_secret(store: State<'_, SecretStore>, name: String, value: String) -> Result<SecretsStatus, String> {
+    store.set(name.trim(), &value)?;
+    Ok(store.status())
+}
+
+#[tauri::command(async)]
+pub fn delete_secret(store: State<'_, SecretStore>, name: String) -> Result<SecretsStatus, String> {
+    store.delete(&name)?;
+    Ok(store.status())
+}
+
+#[tauri::command(async)]
+pub async fn get_config(config: State<'_, Arc<Mutex<ConfigFile>>>) -> Result<ConfigFile, String> {
+    Ok(config.lock().await.clone())
+}
+
+// changes holds only the top level fields to replace, e.g. {"save_on_close": true}
+#[tauri::command(async)]
+pub async fn update_config(
+    app: tauri::AppHandle,
+    changes: serde_json::Map<String, serde_json::Value>,
+) -> Result<ConfigUpdate, String> {
+    config::update(&app, changes).await
+}
+
+// where config, chats and plugins live, shown in the settings
+#[tauri::command]
+pub fn get_data_paths() -> DataPaths {
+    paths::data_paths()
+}
+
+// problems found while loading config.json, the settings page shows them
+#[tauri::command(async)]
+pub fn get_config_errors(errors: State<'_, ConfigErrors>) -> Vec<String> {
+    errors.0.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(names: &[&str]) -> MessageType {
+        let calls: Vec<ToolCallFn> = names
+            .iter()
+            .map(|name| ToolCallFn { name: name.to_string(), arguments: Default::default(), call_id: None })
+            .collect();
+        MessageType::ToolCall(ToolCall { content: serde_json::to_string(&calls).unwrap() })
+    }
+
+    fn response(content: &str) -> MessageType {
+        MessageType::ToolResponse(ToolResponse {
+            content: content.into(),
+            call_id: None,
+            name: String::new(),
+            status: ToolStatus::Success,
+            duration_ms: 1,
+        })
+    }
+
+    fn results(messages: &[Message]) -> Vec<Option<serde_json::Value>> {
+        messages.iter().filter_map(|m| m.tool.as_ref()).map(|t| t.result.clone()).collect()
+    }
+
+    #[test]
+    fn responses_without_ids_answer_their_calls_in_order() {
+        let mut conversation = Conversation::default();
+        conversation.push(MessageType::User(UserMessage::new("hi".to_string())));
+        conversation.push(call(&["a", "b"]));
+        conversation.push(response("first"));
+        conversation.push(response("second"));
+        assert_eq!(results(&transcript(&conversation)), vec![Some("first".into()), Some("second".into())]);
+    }
+
+    #[test]
+    fn an_unanswered_round_does_not_take_a_later_response() {
+        let mut conversation = Conversation::default();
+        conversation.push(MessageType::User(UserMessage::new("hi".to_string())));
+        conversation.push(call(&["a"]));
+        conversation.push(MessageType::User(UserMessage::new("again".to_string())));
+        conversation.push(call(&["b"]));
+        conversation.push(response("late"));
+        assert_eq!(results(&transcript(&conversation)), vec![None, Some("late".into())]);
+    }
+}
@@ -0,0 +1,344 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tauri::{async_runtime::Mutex, Manager as _, State};
+
+use crate::{hotkey, paths::{config_dir, write_atomic}, persona, profile, quick_action, serde_obj::ConfigFile, speech::Speaker, voice::VoiceInput};
+
+// bump this and add a migration whenever a field is renamed or changes meaning,
+// new fields only need a serde default
+pub const CONFIG_VERSION: u32 = 2;
+
+// read once at startup, a change is saved but only used after a restart
+const RESTART_FIELDS: &[&str] = &["builtin_tools", "embedding", "secrets", "api"];
+
+// MIGRATIONS[n] takes a config from version n + 1 to n + 2
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[v1_to_v2];
+
+// version 1 had no version field and save_on_close had no default
+fn v1_to_v2(config: &mut Map<String, Value>) {
+    config.entry("save_on_close").or_insert(Value::Bool(false));
+}
+
+pub fn config_path() -> PathBuf {
+    config_dir().join("config.json")
+}
+
+pub fn default_config() -> ConfigFile {
+    serde_json::from_slice(include_bytes!("cdn/config.json")).expect("the bundled config is valid")
+}
+
+pub struct LoadedConfig {
+    pub config: ConfigFile,
+    // shown in the settings, the app starts with defaults for whatever failed
+    pub errors: Vec<String>,
+}
+
+pub fn migrate(config: &mut Map<String, Value>) -> Result<bool, String> {
+    let version = match config.get("version") {
+        None => 1,
+        Some(version) => version
+            .as_u64()
+            .filter(|v| *v >= 1)
+            .ok_or("version must be a positive number")? as u32,
+    };
+    if version > CONFIG_VERSION {
+        return Err(format!(
+            "config.json is from a newer version of the app (version {}), unknown settings are ignored",
+            version
+        ));
+    }
+    for migration in &MIGRATIONS[(version - 1) as usize..] {
+        migration(config);
+    }
+    config.insert("version".to_string(), CONFIG_VERSION.into());
+    Ok(version != CONFIG_VERSION)
+}
+
+// every top level field is read on its own so one bad value only resets that field
+fn parse(user: Map<String, Value>, errors: &mut Vec<String>) -> ConfigFile {
+    let Value::Object(mut merged) = serde_json::to_value(default_config()).unwrap() else {
+        unreachable!()
+    };
+    for (key, value) in user {
+        let previous = merged.insert(key.clone(), value);
+        if let Err(e) = serde_json::from_value::<ConfigFile>(Value::Object(merged.clone())) {
+            errors.push(format!("{}: {}, using the default", key, e));
+            match previous {
+                Some(previous) => merged.insert(key, previous),
+                None => merged.remove(&key),
+            };
+        }
+    }
+    serde_json::from_value(Value::Object(merged)).unwrap()
+}
+
+// the same checks the save commands run, a failing section falls back to its defaults
+pub fn validate(config: &mut ConfigFile, errors: &mut Vec<String>) {
+    let defaults = default_config();
+    if let Err(e) = persona::validate(&config.personas, &config.default_persona) {
+        errors.push(format!("personas: {}, using the defaults", e));
+        config.personas = defaults.personas;
+        config.default_persona = defaults.default_persona;
+    }
+    if let Err(e) = config.generation.validate() {
+        errors.push(format!("generation: {}, using the defaults", e));
+        config.generation = defaults.generation;
+    }
+    if let Err(e) = profile::validate(&config.profiles, &config.default_profile, &config.routing) {
+        errors.push(format!("profiles: {}, using the defaults", e));
+        config.profiles = defaults.profiles;
+        config.default_profile = defaults.default_profile;
+        config.routing = defaults.routing;
+    }
+    if let Err(e) = config.documents.validate() {
+        errors.push(format!("documents: {}, using the defaults", e));
+        config.documents = defaults.documents;
+    }
+    if let Err(e) = quick_action::validate(&config.quick_actions) {
+        errors.push(format!("quick actions: {}, using the defaults", e));
+        config.quick_actions = defaults.quick_actions;
+    }
+    if let Err(e) = hotkey::validate(&config.hotkeys, &config.quick_actions) {
+        errors.push(format!("hotkeys: {}, using the defaults", e));
+        config.hotkeys = defaults.hotkeys;
+    }
+    if let Err(e) = config.voice.validate() {
+        errors.push(format!("voice: {}, using the defaults", e));
+        config.voice = defaults.voice;
+    }
+    if let Err(e) = config.api.validate() {
+        errors.push(format!("api: {}, using the defaults", e));
+        config.api = defaults.api;
+    }
+}
+
+fn read(path: &Path) -> Result<Map<String, Value>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read config.json: {}", e))?;
+    match serde_json::from_str(&text) {
+        Ok(Value::Object(map)) => Ok(map),
+        Ok(_) => Err("config.json must hold an object".to_string()),
+        Err(e) => Err(format!("config.json is not valid json: {}", e)),
+    }
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    path.with_extension("json.bak")
+}
+
+// the file is only written when it is new or was migrated, never just because the app started
+pub fn load_config(path: &Path) -> LoadedConfig {
+    if !path.exists() {
+        let config = default_config();
+        let errors = write_config(path, &config).err().into_iter().collect();
+        return LoadedConfig { config, errors };
+    }
+    let mut errors = Vec::new();
+    let mut user = read(path).unwrap_or_else(|e| {
+        errors.push(format!("{}, using the defaults", e));
+        Map::new()
+    });
+    let migrated = migrate(&mut user).unwrap_or_else(|e| {
+        errors.push(e);
+        false
+    });
+    let mut config = parse(user, &mut errors);
+    validate(&mut config, &mut errors);
+    if !errors.is_empty() {
+        // the next save writes the defaults over the broken values, keep what the user had
+        let backup = backup_path(path);
+        match std::fs::copy(path, &backup) {
+            Ok(_) => errors.push(format!("the original file was copied to {}", backup.display())),
+            Err(e) => eprintln!("backup config error {}", e),
+        }
+    } else if migrated {
+        if let Err(e) = write_config(path, &config) {
+            errors.push(e);
+        }
+    }
+    LoadedConfig { config, errors }
+}
+
+pub fn write_config(path: &Path, config: &ConfigFile) -> Result<(), String> {
+    let text = serde_json::to_string(config).map_err(|e| e.to_string())?;
+    write_atomic(path, text.as_bytes()).map_err(|e| format!("cannot save config.json: {}", e))
+}
+
+#[derive(Default)]
+pub struct ConfigErrors(pub std::sync::Mutex<Vec<String>>);
+
+// everything a save command checks, the first problem is the error
+pub fn check(config: &ConfigFile) -> Result<(), String> {
+    persona::validate(&config.personas, &config.default_persona)?;
+    config.generation.validate()?;
+    profile::validate(&config.profiles, &config.default_profile, &config.routing)?;
+    config.documents.validate()?;
+    quick_action::validate(&config.quick_actions)?;
+    hotkey::validate(&config.hotkeys, &config.quick_actions)?;
+    config.voice.validate()?;
+    config.api.validate()
+}
+
+// every window gets the new config, e.g. the chat reloads its profile list
+pub fn save(app: &tauri::AppHandle, config: &ConfigFile) -> Result<(), String> {
+    config.clone().save_to_file(&config_path(), Some(app.clone()))?;
+    if let Err(e) = app.emit_all("config-changed", config) {
+        eprintln!("emit config-changed error {}", e);
+    }
+    Ok(())
+}
+
+fn changed(old: &Value, new: &Value, key: &str) -> bool {
+    old.get(key) != new.get(key)
+}
+
+// the same side effects the section save commands have, returns whether a restart is needed
+fn apply_live(app: &tauri::AppHandle, old: &ConfigFile, new: &ConfigFile) -> Result<bool, String> {
+    let old_value = serde_json::to_value(old).unwrap();
+    let new_value = serde_json::to_value(new).unwrap();
+    if changed(&old_value, &new_value, "speech") {
+        app.state::<Speaker>().set_config(&new.speech)?;
+    }
+    if changed(&old_value, &new_value, "voice") {
+        app.state::<VoiceInput>().reset();
+    }
+    if changed(&old_value, &new_value, "quick_actions") {
+        if let Err(e) = app.tray_handle().set_menu(crate::create_tray_menu(new)) {
+            eprintln!("update tray menu error {}", e);
+        }
+    }
+    if changed(&old_value, &new_value, "quick_actions") || changed(&old_value, &new_value, "hotkeys") {
+        for error in hotkey::apply(app, &new.hotkeys) {
+            eprintln!("register hotkey {} for {} error {}", error.shortcut, error.action, error.error);
+        }
+    }
+    Ok(RESTART_FIELDS.iter().any(|key| changed(&old_value, &new_value, key))
+        || old.context.tokenizer_path != new.context.tokenizer_path)
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ConfigUpdate {
+    pub config: ConfigFile,
+    pub restart_required: bool,
+}
+
+// top level fields from the settings page, checked as a whole before anything changes
+pub async fn update(app: &tauri::AppHandle, changes: Map<String, Value>) -> Result<ConfigUpdate, String> {
+    let config_s: State<Arc<Mutex<ConfigFile>>> = app.state();
+    let mut config = config_s.lock().await;
+    let Value::Object(mut merged) = serde_json::to_value(&*config).unwrap() else {
+        unreachable!()
+    };
+    for (key, value) in changes {
+        if key == "version" {
+            return Err("version cannot be changed".to_string());
+        }
+        if !merged.contains_key(&key) {
+            return Err(format!("unknown setting {}", key));
+        }
+        merged.insert(key, value);
+    }
+    let mut new: ConfigFile = serde_json::from_value(Value::Object(merged)).map_err(|e| e.to_string())?;
+    // hotkeys of removed actions go away with them
+    let available = hotkey::available_actions(&new.quick_actions);
+    new.hotkeys.retain(|action, _| available.iter().any(|a| &a.id == action));
+    check(&new)?;
+    // the file goes first so a failed write changes nothing, the tray and hotkeys
+    // wait for the main thread which may be waiting for this lock
+    save(app, &new)?;
+    let old = std::mem::replace(&mut *config, new.clone());
+    drop(config);
+    let restart_required = apply_live(app, &old, &new)?;
+    Ok(ConfigUpdate { config: new, restart_required })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_config(text: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rasast-config-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.json");
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn version_one_is_migrated_and_saved() {
+        let path = temp_config(r#"{"run_on_startup":true}"#);
+        let loaded = load_config(&path);
+        assert!(loaded.errors.is_empty(), "{:?}", loaded.errors);
+        assert!(loaded.config.run_on_startup);
+        assert!(!loaded.config.save_on_close);
+        let saved: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved["version"], CONFIG_VERSION);
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn current_file_is_not_rewritten() {
+        let text = format!(r#"{{"version":{},"save_on_close":true}}"#, CONFIG_VERSION);
+        let path = temp_config(&text);
+        let loaded = load_config(&path);
+        assert!(loaded.errors.is_empty());
+        assert!(loaded.config.save_on_close);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), text);
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn bad_values_only_reset_their_field() {
+        let path = temp_config(r#"{"version":2,"save_on_close":true,"run_on_startup":"yes","generation":{"temperature":9}}"#);
+        let loaded = load_config(&path);
+        assert!(loaded.config.save_on_close);
+        assert!(!loaded.config.run_on_startup);
+        assert_eq!(loaded.config.generation.temperature, default_config().generation.temperature);
+        assert!(loaded.errors.iter().any(|e| e.starts_with("run_on_startup")));
+        assert!(loaded.errors.iter().any(|e| e.starts_with("generation")));
+        assert!(backup_path(&path).exists());
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn documents_and_voice_are_checked_on_load() {
+        let path = temp_config(
+            r#"{"version":2,"save_on_close":true,"documents":{"chunk_chars":0},"voice":{"model_path":"/no/such/model.bin"}}"#,
+        );
+        let loaded = load_config(&path);
+        assert!(loaded.config.save_on_close);
+        assert_eq!(loaded.config.documents.chunk_chars, default_config().documents.chunk_chars);
+        assert_eq!(loaded.config.voice.model_path, None);
+        assert!(loaded.errors.iter().any(|e| e.starts_with("documents: chunk_chars")));
+        assert!(loaded.errors.iter().any(|e| e.starts_with("voice: model")));
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn broken_json_is_an_error_not_a_panic() {
+        let path = temp_config("{\"save_on_close\": tru");
+        let loaded = load_config(&path);
+        assert!(loaded.errors[0].starts_with("config.json is not valid json"));
+        assert_eq!(loaded.config.save_on_close, default_config().save_on_close);
+        assert!(backup_path(&path).exists());
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn newer_versions_are_reported() {
+        let mut config = Map::new();
+        config.insert("version".to_string(), Value::from(CONFIG_VERSION + 1));
+        assert!(migrate(&mut config).is_err());
+    }
+
+    #[test]
+    fn bundled_config_lists_the_quick_actions() {
+        // default_quick_actions reads them from here, a missing field would make it call itself
+        let bundled: Map<String, Value> = serde_json::from_slice(include_bytes!("cdn/config.json")).unwrap();
+        assert!(!bundled["quick_actions"].as_array().unwrap().is_empty());
+    }
+}
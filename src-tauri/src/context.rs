@@ -0,0 +1,287 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::tokenizer::{MessageType, SystemMessage};
+
+const DIGEST_MAX_ENTRIES: usize = 16;
+const DIGEST_ENTRY_CHARS: usize = 120;
+
+pub trait TokenCounter: Send + Sync {
+    fn count(&self, text: &str) -> usize;
+}
+
+// sentencepiece vocabularies average a bit over 3 chars per token, so this overcounts on purpose
+pub struct EstimateCounter {
+    chars_per_token: f32,
+}
+
+impl Default for EstimateCounter {
+    fn default() -> Self {
+        Self { chars_per_token: 3.0 }
+    }
+}
+
+impl TokenCounter for EstimateCounter {
+    fn count(&self, text: &str) -> usize {
+        (text.chars().count() as f32 / self.chars_per_token).ceil() as usize
+    }
+}
+
+pub struct HfTokenCounter {
+    tokenizer: tokenizers::Tokenizer,
+}
+
+impl TokenCounter for HfTokenCounter {
+    fn count(&self, text: &str) -> usize {
+        match self.tokenizer.encode(text, false) {
+            Ok(encoding) => encoding.len(),
+            Err(_) => EstimateCounter::default().count(text),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncationPolicy {
+    #[default]
+    DropOldest,
+    Summarize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ContextConfig {
+    #[serde(default = "default_max_tokens")]
+    pub max_tokens: usize,
+    // room left for the reply
+    #[serde(default = "default_reserve_tokens")]
+    pub reserve_tokens: usize,
+    #[serde(default)]
+    pub truncation: TruncationPolicy,
+    // path to a huggingface tokenizer.json, the estimator is used when empty
+    #[serde(default)]
+    pub tokenizer_path: Option<String>,
+}
+
+fn default_max_tokens() -> usize {
+    8192
+}
+
+fn default_reserve_tokens() -> usize {
+    1024
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: default_max_tokens(),
+            reserve_tokens: default_reserve_tokens(),
+            truncation: TruncationPolicy::default(),
+            tokenizer_path: None,
+        }
+    }
+}
+
+pub fn load_token_counter(config: &ContextConfig) -> Arc<dyn TokenCounter> {
+    if let Some(path) = &config.tokenizer_path {
+        match tokenizers::Tokenizer::from_file(path) {
+            Ok(tokenizer) => return Arc::new(HfTokenCounter { tokenizer }),
+            Err(e) => eprintln!("load tokenizer {} error {} use estimator", path, e),
+        }
+    }
+    Arc::new(EstimateCounter::default())
+}
+
+// system messages are pinned, everything else is grouped into turns that start at a user message
+// so a tool call is never separated from its result
+fn split_turns(messages: Vec<MessageType>) -> (Vec<MessageType>, Vec<Vec<MessageType>>) {
+    let mut pinned = Vec::new();
+    let mut turns: Vec<Vec<MessageType>> = Vec::new();
+    for message in messages {
+        match message {
+            MessageType::System(_) => pinned.push(message),
+            MessageType::User(_) => turns.push(vec![message]),
+            _ => match turns.last_mut() {
+                Some(turn) => turn.push(message),
+                None => turns.push(vec![message]),
+            },
+        }
+    }
+    (pinned, turns)
+}
+
+fn shorten(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if text.chars().count() <= DIGEST_ENTRY_CHARS {
+        return text;
+    }
+    let mut short: String = text.chars().take(DIGEST_ENTRY_CHARS).collect();
+    short.push('…');
+    short
+}
+
+fn digest_turns(dropped: &[Vec<MessageType>]) -> Option<SystemMessage> {
+    let mut lines = Vec::new();
+    for message in dropped.iter().flatten() {
+        match message {
+            MessageType::User(m) => lines.push(format!("- User: {}", shorten(&m.content))),
+            MessageType::Assistant(m) => lines.push(format!("- Assistant: {}", shorten(&m.content))),
+            _ => {}
+        }
+    }
+    if lines.is_empty() {
+        return None;
+    }
+    let skip = lines.len().saturating_sub(DIGEST_MAX_ENTRIES);
+    Some(SystemMessage {
+        content: format!(
+            "Earlier messages that no longer fit in the context:\n{}",
+            lines[skip..].join("\n")
+        ),
+    })
+}
+
+fn assemble(pinned: &[MessageType], digest: Option<SystemMessage>, turns: &[Vec<MessageType>]) -> Vec<MessageType> {
+    let mut messages = pinned.to_vec();
+    if let Some(digest) = digest {
+        messages.push(MessageType::System(digest));
+    }
+    messages.extend(turns.iter().flatten().cloned());
+    messages
+}
+
+// drops the oldest turns until the rendered prompt fits, the latest turn is always kept
+pub fn fit_messages<F>(
+    messages: Vec<MessageType>,
+    render: F,
+    counter: &dyn TokenCounter,
+    config: &ContextConfig,
+) -> Vec<MessageType>
+where
+    F: Fn(&[MessageType]) -> String,
+{
+    let limit = config.max_tokens.saturating_sub(config.reserve_tokens);
+    let (pinned, mut turns) = split_turns(messages);
+    let mut dropped = Vec::new();
+    loop {
+        let digest = match config.truncation {
+            TruncationPolicy::Summarize => digest_turns(&dropped),
+            TruncationPolicy::DropOldest => None,
+        };
+        let candidate = assemble(&pinned, digest, &turns);
+        if turns.len() <= 1 || counter.count(&render(&candidate)) <= limit {
+            return candidate;
+        }
+        dropped.push(turns.remove(0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        serde_obj::ToolStatus,
+        tokenizer::{AssistantMessage, ToolCall, ToolResponse, UserMessage},
+    };
+
+    // one token per word keeps the budgets easy to follow
+    struct WordCounter;
+
+    impl TokenCounter for WordCounter {
+        fn count(&self, text: &str) -> usize {
+            text.split_whitespace().count()
+        }
+    }
+
+    fn render(messages: &[MessageType]) -> String {
+        messages
+            .iter()
+            .map(|m| match m {
+                MessageType::User(m) => m.content.clone(),
+                MessageType::Assistant(m) => m.content.clone(),
+                MessageType::System(m) => m.content.clone(),
+                MessageType::ToolCall(m) => m.content.clone(),
+                MessageType::ToolResponse(m) => m.content.to_string(),
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    fn user(content: &str) -> MessageType {
+        MessageType::User(UserMessage::new(content.to_string()))
+    }
+
+    fn assistant(content: &str) -> MessageType {
+        MessageType::Assistant(AssistantMessage { content: content.to_string(), model: None })
+    }
+
+    fn config(max_tokens: usize, truncation: TruncationPolicy) -> ContextConfig {
+        ContextConfig { max_tokens, reserve_tokens: 0, truncation, tokenizer_path: None }
+    }
+
+    fn contents(messages: &[MessageType]) -> Vec<String> {
+        messages.iter().map(|m| render(std::slice::from_ref(m))).collect()
+    }
+
+    #[test]
+    fn drops_oldest_turns_and_keeps_system_prompt() {
+        let messages = vec![
+            MessageType::System(SystemMessage { content: "be brief".to_string() }),
+            user("one one"),
+            assistant("two two"),
+            user("three three"),
+            assistant("four four"),
+        ];
+        let fitted = fit_messages(messages.clone(), render, &WordCounter, &config(100, TruncationPolicy::DropOldest));
+        assert_eq!(fitted, messages);
+        let fitted = fit_messages(messages, render, &WordCounter, &config(6, TruncationPolicy::DropOldest));
+        assert_eq!(contents(&fitted), vec!["be brief", "three three", "four four"]);
+    }
+
+    #[test]
+    fn latest_turn_is_kept_even_when_too_long() {
+        let messages = vec![user("a b c"), assistant("d e f"), user("g h i j k")];
+        let fitted = fit_messages(messages, render, &WordCounter, &config(2, TruncationPolicy::DropOldest));
+        assert_eq!(contents(&fitted), vec!["g h i j k"]);
+    }
+
+    #[test]
+    fn tool_call_stays_with_its_response() {
+        let messages = vec![
+            user("old question"),
+            assistant("old answer"),
+            user("what time"),
+            MessageType::ToolCall(ToolCall { content: "now".to_string() }),
+            MessageType::ToolResponse(ToolResponse {
+                content: serde_json::json!("noon"),
+                call_id: None,
+                name: "now".to_string(),
+                status: ToolStatus::Success,
+                duration_ms: 0,
+            }),
+            assistant("it is noon"),
+        ];
+        let fitted = fit_messages(messages, render, &WordCounter, &config(7, TruncationPolicy::DropOldest));
+        assert_eq!(contents(&fitted), vec!["what time", "now", "\"noon\"", "it is noon"]);
+    }
+
+    #[test]
+    fn summarize_replaces_dropped_turns_with_a_digest() {
+        let messages = vec![user("first"), assistant("reply"), user("second")];
+        let fitted = fit_messages(messages, render, &WordCounter, &config(20, TruncationPolicy::Summarize));
+        assert_eq!(fitted.len(), 3);
+        let fitted = fit_messages(
+            vec![user("first question here"), assistant("reply"), user("second")],
+            render,
+            &WordCounter,
+            &config(4, TruncationPolicy::Summarize),
+        );
+        let digest = match &fitted[0] {
+            MessageType::System(m) => m.content.clone(),
+            other => panic!("expected a digest, got {:?}", other),
+        };
+        assert!(digest.contains("- User: first question here"));
+        assert!(digest.contains("- Assistant: reply"));
+        assert_eq!(contents(&fitted[1..]), vec!["second"]);
+    }
+}
@@ -0,0 +1,398 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::{async_runtime::Mutex, Manager as _, State};
+
+use crate::{
+    generation::GenerationParams,
+    paths::{data_dir, write_atomic},
+    serde_obj::ConfigFile,
+    tokenizer::{MessageType, SystemMessage},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConversationSummary {
+    pub content: String,
+    // number of messages from the start of the active branch that the summary replaces
+    pub covered: usize,
+    // last node the summary covers, it only applies while that node is on the active branch
+    #[serde(default)]
+    pub anchor: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageNode {
+    pub parent: Option<usize>,
+    pub message: MessageType,
+    // child followed when walking the active branch
+    #[serde(default)]
+    pub active_child: Option<usize>,
+    #[serde(default)]
+    pub created_at: i64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Conversation {
+    pub id: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub created_at: i64,
+    #[serde(default)]
+    pub updated_at: i64,
+    // persona name from the config, None means the default persona
+    #[serde(default)]
+    pub persona: Option<String>,
+    // overrides on top of the generation settings from the config
+    #[serde(default)]
+    pub params: Option<GenerationParams>,
+    // model profile name from the config, None means the routing rules decide
+    #[serde(default)]
+    pub profile: Option<String>,
+    // every message ever written, edits and regenerations become siblings
+    #[serde(default)]
+    pub nodes: Vec<MessageNode>,
+    #[serde(default)]
+    pub active_root: Option<usize>,
+    #[serde(default)]
+    pub summary: Option<ConversationSummary>,
+    // flat history written before branching existed, moved into nodes on load
+    #[serde(default, skip_serializing)]
+    messages: Vec<MessageType>,
+}
+
+impl Default for Conversation {
+    fn default() -> Self {
+        let now = chrono::Utc::now().timestamp();
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            title: None,
+            created_at: now,
+            updated_at: now,
+            persona: None,
+            params: None,
+            profile: None,
+            nodes: Vec::new(),
+            active_root: None,
+            summary: None,
+            messages: Vec::new(),
+        }
+    }
+}
+
+impl Conversation {
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // node ids from the root to the leaf of the active branch
+    pub fn active_path(&self) -> Vec<usize> {
+        let mut path = Vec::new();
+        let mut current = self.active_root;
+        while let Some(idx) = current {
+            path.push(idx);
+            current = self.nodes[idx].active_child;
+        }
+        path
+    }
+
+    pub fn messages(&self) -> Vec<MessageType> {
+        self.active_path()
+            .into_iter()
+            .map(|idx| self.nodes[idx].message.clone())
+            .collect()
+    }
+
+    pub fn get(&self, node: usize) -> Option<&MessageNode> {
+        self.nodes.get(node)
+    }
+
+    // append after the leaf of the active branch
+    pub fn push(&mut self, message: MessageType) -> usize {
+        let parent = self.active_path().last().copied();
+        self.add_child(parent, message)
+    }
+
+    // add a new child under `parent` (None for a new root) and make it active
+    pub fn add_child(&mut self, parent: Option<usize>, message: MessageType) -> usize {
+        let idx = self.nodes.len();
+        self.nodes.push(MessageNode {
+            parent,
+            message,
+            active_child: None,
+            created_at: chrono::Utc::now().timestamp(),
+        });
+        self.select(idx);
+        idx
+    }
+
+    // make `node` part of the active branch, the branch below it keeps its last selection
+    pub fn select(&mut self, node: usize) {
+        let mut child = node;
+        let mut parent = self.nodes[node].parent;
+        while let Some(idx) = parent {
+            self.nodes[idx].active_child = Some(child);
+            child = idx;
+            parent = self.nodes[idx].parent;
+        }
+        self.active_root = Some(child);
+    }
+
+    // cut the active branch right after `node` so the next push becomes a sibling of its old child
+    pub fn truncate_after(&mut self, node: usize) {
+        self.select(node);
+        self.nodes[node].active_child = None;
+    }
+
+    pub fn siblings(&self, node: usize) -> Vec<usize> {
+        let parent = self.nodes[node].parent;
+        (0..self.nodes.len())
+            .filter(|idx| self.nodes[*idx].parent == parent)
+            .collect()
+    }
+
+    // the summary only counts while the messages it replaces are on the active branch
+    pub fn active_summary(&self) -> Option<&ConversationSummary> {
+        let path = self.active_path();
+        self.summary.as_ref().filter(|s| {
+            s.covered <= path.len()
+                && (s.anchor.is_none() || (s.covered > 0 && s.anchor == Some(path[s.covered - 1])))
+        })
+    }
+
+    // what gets sent to the model, summarized messages are swapped for the summary
+    pub fn prompt_messages(&self) -> Vec<MessageType> {
+        let messages = self.messages();
+        let Some(summary) = self.active_summary() else {
+            return messages;
+        };
+        let mut prompt = vec![MessageType::System(SystemMessage {
+            content: format!("Summary of the earlier conversation:\n{}", summary.content),
+        })];
+        prompt.extend_from_slice(&messages[summary.covered..]);
+        prompt
+    }
+
+    fn migrate_flat_history(&mut self) {
+        for message in std::mem::take(&mut self.messages) {
+            self.push(message);
+        }
+    }
+
+    pub fn save(&mut self) -> Result<(), String> {
+        self.updated_at = chrono::Utc::now().timestamp();
+        self.write()
+    }
+
+    // write as is, imports keep the times they came with
+    pub fn write(&self) -> Result<(), String> {
+        let dir = get_conversation_dir();
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let data = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        write_atomic(&dir.join(format!("{}.json", self.id)), data.as_bytes()).map_err(|e| e.to_string())
+    }
+
+    // every walk indexes nodes directly, so a cut or hand edited file must not get that far
+    fn check_tree(&self) -> Result<(), String> {
+        let len = self.nodes.len();
+        if self.active_root.is_some_and(|root| root >= len || self.nodes[root].parent.is_some()) {
+            return Err("the active root is not a root message".to_string());
+        }
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if node.parent.is_some_and(|parent| parent >= len) {
+                return Err(format!("message {} has a missing parent", idx));
+            }
+            if node.active_child.is_some_and(|child| child >= len || self.nodes[child].parent != Some(idx)) {
+                return Err(format!("message {} follows a message that is not its child", idx));
+            }
+            // a chain longer than the number of messages goes around in a circle
+            let mut parent = node.parent;
+            let mut steps = 0;
+            while let Some(p) = parent {
+                steps += 1;
+                if steps > len {
+                    return Err(format!("message {} is its own ancestor", idx));
+                }
+                parent = self.nodes[p].parent;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_file(path: &Path) -> Option<Conversation> {
+        let mut conversation: Conversation = serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()?;
+        if let Err(e) = conversation.check_tree() {
+            eprintln!("skip conversation {}: {}", path.display(), e);
+            return None;
+        }
+        conversation.migrate_flat_history();
+        Some(conversation)
+    }
+
+    pub fn load_all() -> Vec<Conversation> {
+        let Ok(files) = std::fs::read_dir(get_conversation_dir()) else {
+            return Vec::new();
+        };
+        files
+            .filter_map(|file| {
+                let path = file.ok()?.path();
+                if path.extension()? != "json" {
+                    return None;
+                }
+                Conversation::read_file(&path)
+            })
+            .collect()
+    }
+
+    pub fn load_latest() -> Option<Conversation> {
+        Conversation::load_all().into_iter().max_by_key(|c| c.updated_at)
+    }
+
+    // ids come from the frontend, anything that is not a uuid could point outside the folder
+    pub fn load(id: &str) -> Option<Conversation> {
+        let id = uuid::Uuid::parse_str(id).ok()?;
+        Conversation::read_file(&get_conversation_dir().join(format!("{}.json", id)))
+    }
+}
+
+pub fn get_conversation_dir() -> PathBuf {
+    data_dir().join("conversations")
+}
+
+// history only goes to disk when the user asked to keep it
+pub async fn save_current(app: &tauri::AppHandle) {
+    let config: State<Arc<Mutex<ConfigFile>>> = app.state();
+    if !config.lock().await.save_on_close {
+        return;
+    }
+    let conversation: State<Arc<Mutex<Conversation>>> = app.state();
+    let mut conversation = conversation.lock().await;
+    if conversation.is_empty() {
+        return;
+    }
+    if let Err(e) = conversation.save() {
+        eprintln!("save conversation {} error {}", conversation.id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::{AssistantMessage, UserMessage};
+
+    fn user(content: &str) -> MessageType {
+        MessageType::User(UserMessage::new(content.to_string()))
+    }
+
+    fn assistant(content: &str) -> MessageType {
+        MessageType::Assistant(AssistantMessage { content: content.to_string(), model: None })
+    }
+
+    fn contents(conversation: &Conversation) -> Vec<String> {
+        conversation
+            .messages()
+            .into_iter()
+            .map(|m| match m {
+                MessageType::User(m) => m.content,
+                MessageType::Assistant(m) => m.content,
+                other => panic!("unexpected message {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn push_follows_the_active_branch() {
+        let mut conversation = Conversation::default();
+        let question = conversation.push(user("hi"));
+        let answer = conversation.push(assistant("hello"));
+        assert_eq!(conversation.active_path(), vec![question, answer]);
+        assert_eq!(conversation.nodes[answer].parent, Some(question));
+        assert_eq!(contents(&conversation), vec!["hi", "hello"]);
+    }
+
+    #[test]
+    fn regenerate_adds_a_sibling_and_select_switches_back() {
+        let mut conversation = Conversation::default();
+        let question = conversation.push(user("hi"));
+        let first = conversation.push(assistant("hello"));
+        let follow_up = conversation.push(user("how are you"));
+        conversation.truncate_after(question);
+        assert_eq!(conversation.active_path(), vec![question]);
+        let second = conversation.push(assistant("hey"));
+        assert_eq!(conversation.siblings(first), vec![first, second]);
+        assert_eq!(contents(&conversation), vec!["hi", "hey"]);
+        // the old branch keeps its own selection below the switched node
+        conversation.select(first);
+        assert_eq!(conversation.active_path(), vec![question, first, follow_up]);
+        conversation.select(second);
+        assert_eq!(conversation.active_path(), vec![question, second]);
+    }
+
+    #[test]
+    fn edited_first_message_becomes_a_new_root() {
+        let mut conversation = Conversation::default();
+        let original = conversation.push(user("hi"));
+        conversation.push(assistant("hello"));
+        let edited = conversation.add_child(None, user("hi there"));
+        assert_eq!(conversation.siblings(original), vec![original, edited]);
+        assert_eq!(conversation.active_path(), vec![edited]);
+        conversation.select(original);
+        assert_eq!(contents(&conversation), vec!["hi", "hello"]);
+    }
+
+    #[test]
+    fn broken_trees_are_rejected() {
+        let mut conversation = Conversation::default();
+        let question = conversation.push(user("hi"));
+        let answer = conversation.push(assistant("hello"));
+        assert!(conversation.check_tree().is_ok());
+        let mut cut = conversation.clone();
+        cut.nodes.truncate(1);
+        assert!(cut.check_tree().is_err());
+        let mut out_of_range = conversation.clone();
+        out_of_range.active_root = Some(5);
+        assert!(out_of_range.check_tree().is_err());
+        let mut circle = conversation.clone();
+        circle.nodes[question].parent = Some(answer);
+        assert!(circle.check_tree().is_err());
+        let mut stray = conversation.clone();
+        stray.nodes[answer].active_child = Some(question);
+        assert!(stray.check_tree().is_err());
+    }
+
+    #[test]
+    fn damaged_files_are_not_loaded() {
+        let dir = std::env::temp_dir().join(format!("rasast-conversation-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut conversation = Conversation::default();
+        conversation.push(user("hi"));
+        conversation.push(assistant("hello"));
+        let good = dir.join("good.json");
+        write_atomic(&good, serde_json::to_string(&conversation).unwrap().as_bytes()).unwrap();
+        assert_eq!(Conversation::read_file(&good).unwrap().nodes, conversation.nodes);
+        conversation.nodes[0].active_child = Some(0);
+        let looped = dir.join("looped.json");
+        write_atomic(&looped, serde_json::to_string(&conversation).unwrap().as_bytes()).unwrap();
+        assert!(Conversation::read_file(&looped).is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn flat_history_is_migrated_into_one_branch() {
+        let mut conversation: Conversation = serde_json::from_str(
+            r#"{"id": "old", "messages": [{"role": "user", "content": "hi"}, {"role": "assistant", "content": "hello"}]}"#,
+        )
+        .unwrap();
+        assert!(conversation.is_empty());
+        conversation.migrate_flat_history();
+        assert_eq!(conversation.active_path(), vec![0, 1]);
+        assert_eq!(contents(&conversation), vec!["hi", "hello"]);
+        assert!(conversation.messages.is_empty());
+        // migrating again does not duplicate anything
+        conversation.migrate_flat_history();
+        assert_eq!(conversation.nodes.len(), 2);
+    }
+}
@@ -0,0 +1,265 @@
+use std::sync::Arc;
+
+use reqwest::Url;
+use tauri::{async_runtime::Mutex, Manager as _, State};
+
+use crate::{
+    commands::generate_reply,
+    conversation::Conversation,
+    hotkey::new_chat,
+    paths::plugins_dir,
+    profile::RouteTask,
+    tokenizer::{MessageType, UserMessage},
+};
+
+pub const SCHEME: &str = "aihelper";
+const MAX_PROMPT_CHARS: usize = 8000;
+const MAX_PLUGIN_BYTES: u64 = 100 * 1024 * 1024;
+const DOWNLOAD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeepLink {
+    // aihelper://ask?q=<text>&new=1, new starts a new chat first
+    Ask { prompt: String, new_chat: bool },
+    // aihelper://chat/<conversation id>
+    Chat { id: String },
+    // aihelper://install-plugin?url=<https url of the library>
+    InstallPlugin { url: Url, file_name: String },
+    // aihelper://settings
+    Settings,
+}
+
+fn valid_plugin_name(name: &str) -> bool {
+    let extension = format!(".{}", std::env::consts::DLL_EXTENSION);
+    name.len() > extension.len()
+        && name.ends_with(&extension)
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+// browsers on windows add a slash, so aihelper://ask/?q= is the same as aihelper://ask?q=
+pub fn parse(link: &str) -> Result<DeepLink, String> {
+    let url = Url::parse(link.trim()).map_err(|e| format!("{} is not a valid link: {}", link, e))?;
+    if url.scheme() != SCHEME {
+        return Err(format!("{} is not an {} link", link, SCHEME));
+    }
+    let action = url.host_str().unwrap_or_default();
+    let path: Vec<&str> = url.path().split('/').filter(|p| !p.is_empty()).collect();
+    let query = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    match (action, path.as_slice()) {
+        ("ask", []) => {
+            let prompt = query("q")
+                .map(|q| q.trim().to_string())
+                .filter(|q| !q.is_empty())
+                .ok_or("ask needs a question in q")?;
+            if prompt.chars().count() > MAX_PROMPT_CHARS {
+                return Err(format!("the question is longer than {} characters", MAX_PROMPT_CHARS));
+            }
+            let new_chat = matches!(query("new").as_deref(), Some("1") | Some("true"));
+            Ok(DeepLink::Ask { prompt, new_chat })
+        }
+        ("chat", [id]) => {
+            let id = uuid::Uuid::parse_str(id).map_err(|_| format!("{} is not a chat id", id))?;
+            Ok(DeepLink::Chat { id: id.to_string() })
+        }
+        ("install-plugin", []) => {
+            let raw = query("url").ok_or("install-plugin needs a url")?;
+            let url = Url::parse(&raw).map_err(|e| format!("{} is not a valid url: {}", raw, e))?;
+            if url.scheme() != "https" {
+                return Err("plugins are only installed over https".to_string());
+            }
+            let file_name = url
+                .path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .unwrap_or_default()
+                .to_string();
+            if !valid_plugin_name(&file_name) {
+                return Err(format!(
+                    "{} is not a plugin for this system, expected a .{} file",
+                    file_name,
+                    std::env::consts::DLL_EXTENSION
+                ));
+            }
+            Ok(DeepLink::InstallPlugin { url, file_name })
+        }
+        ("settings", []) => Ok(DeepLink::Settings),
+        _ => Err(format!("unknown link {}", link)),
+    }
+}
+
+// links can come from any web page, so everything that runs or installs something asks first
+async fn confirm(app: &tauri::AppHandle, title: &'static str, message: String) -> bool {
+    let app = app.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let parent = app.get_window("main");
+        tauri::api::dialog::blocking::ask(parent.as_ref(), title, message)
+    })
+    .await
+    .unwrap_or(false)
+}
+
+fn notify(app: &tauri::AppHandle, title: &str, message: &str) {
+    let parent = app.get_window("main");
+    tauri::api::dialog::message(parent.as_ref(), title, message);
+}
+
+fn shorten(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((idx, _)) => format!("{}…", &text[..idx]),
+        None => text.to_string(),
+    }
+}
+
+async fn ask(app: &tauri::AppHandle, prompt: String, start_new: bool) -> Result<(), String> {
+    let message = format!("Send this question to the assistant?\n\n{}", shorten(&prompt, 500));
+    if !confirm(app, "Ask from a link", message).await {
+        return Ok(());
+    }
+    if start_new {
+        new_chat(app).await;
+    }
+    crate::create_main_window(app.clone());
+    let conversation: State<Arc<Mutex<Conversation>>> = app.state();
+    conversation.lock().await.push(MessageType::User(UserMessage::new(prompt)));
+    let _ = app.emit_all("conversation-changed", ());
+    let _ = generate_reply(app.clone(), uuid::Uuid::new_v4().to_string(), RouteTask::Chat).await;
+    let _ = app.emit_all("conversation-changed", ());
+    Ok(())
+}
+
+async fn open_chat(app: &tauri::AppHandle, id: String) -> Result<(), String> {
+    crate::commands::open_conversation(app.clone(), id, None).await?;
+    crate::create_main_window(app.clone());
+    let _ = app.emit_all("conversation-changed", ());
+    Ok(())
+}
+
+// read in chunks so a server that lies about the size stops at the limit
+async fn download(url: Url) -> Result<Vec<u8>, String> {
+    let too_large = || "the plugin is larger than 100 MB".to_string();
+    let client = reqwest::Client::builder()
+        .timeout(DOWNLOAD_TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut res = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| e.to_string())?;
+    if res.content_length().is_some_and(|len| len > MAX_PLUGIN_BYTES) {
+        return Err(too_large());
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = res.chunk().await.map_err(|e| e.to_string())? {
+        if (bytes.len() + chunk.len()) as u64 > MAX_PLUGIN_BYTES {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+// plugins are loaded at startup, so a new one is only used after a restart
+async fn install_plugin(app: &tauri::AppHandle, url: Url, file_name: String) -> Result<(), String> {
+    let target = plugins_dir().join(&file_name);
+    if target.exists() {
+        return Err(format!("a plugin named {} is already installed", file_name));
+    }
+    let message = format!(
+        "Install the plugin {} from {}?\n\nPlugins run as native code with full access to this computer, only install plugins you trust.",
+        file_name,
+        url.host_str().unwrap_or_default()
+    );
+    if !confirm(app, "Install plugin", message).await {
+        return Ok(());
+    }
+    let bytes = download(url).await?;
+    std::fs::create_dir_all(plugins_dir()).map_err(|e| e.to_string())?;
+    let tmp = target.with_extension("download");
+    std::fs::write(&tmp, bytes).map_err(|e| e.to_string())?;
+    std::fs::rename(&tmp, &target).map_err(|e| e.to_string())?;
+    notify(app, "Plugin installed", &format!("{} is loaded the next time the app starts.", file_name));
+    Ok(())
+}
+
+pub async fn run(app: &tauri::AppHandle, link: DeepLink) -> Result<(), String> {
+    match link {
+        DeepLink::Ask { prompt, new_chat } => ask(app, prompt, new_chat).await,
+        DeepLink::Chat { id } => open_chat(app, id).await,
+        DeepLink::InstallPlugin { url, file_name } => install_plugin(app, url, file_name).await,
+        DeepLink::Settings => {
+            crate::create_setting_window(app.clone());
+            Ok(())
+        }
+    }
+}
+
+pub async fn handle(app: &tauri::AppHandle, link: &str) {
+    let result = match parse(link) {
+        Ok(action) => run(app, action).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        eprintln!("deep link {} error {}", link, e);
+        notify(app, "Link not opened", &e);
+    }
+}
+
+// the link is an argument when the os starts the app for it
+pub fn find_in_args(args: &[String]) -> Option<&String> {
+    args.iter().find(|arg| arg.starts_with(&format!("{}://", SCHEME)))
+}
+
+pub fn spawn_handle(app: &tauri::AppHandle, link: String) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move { handle(&app, &link).await });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_action() {
+        assert_eq!(
+            parse("aihelper://ask?q=what%20is%20rust%3F").unwrap(),
+            DeepLink::Ask { prompt: "what is rust?".to_string(), new_chat: false }
+        );
+        assert_eq!(
+            parse("aihelper://ask/?q=hi&new=1").unwrap(),
+            DeepLink::Ask { prompt: "hi".to_string(), new_chat: true }
+        );
+        let id = uuid::Uuid::new_v4().to_string();
+        assert_eq!(parse(&format!("aihelper://chat/{}", id)).unwrap(), DeepLink::Chat { id });
+        assert_eq!(parse("aihelper://settings/").unwrap(), DeepLink::Settings);
+        let name = format!("tool.{}", std::env::consts::DLL_EXTENSION);
+        let link = format!("aihelper://install-plugin?url=https%3A%2F%2Fexample.com%2Fdl%2F{}", name);
+        match parse(&link).unwrap() {
+            DeepLink::InstallPlugin { url, file_name } => {
+                assert_eq!(file_name, name);
+                assert_eq!(url.host_str(), Some("example.com"));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_links() {
+        assert!(parse("https://ask?q=hi").is_err());
+        assert!(parse("aihelper://ask").is_err());
+        assert!(parse("aihelper://ask?q=%20%20").is_err());
+        assert!(parse("aihelper://chat/../../etc/passwd").is_err());
+        assert!(parse("aihelper://chat/not-an-id").is_err());
+        assert!(parse("aihelper://settings/extra").is_err());
+        assert!(parse("aihelper://run?cmd=ls").is_err());
+        let name = format!("tool.{}", std::env::consts::DLL_EXTENSION);
+        assert!(parse(&format!("aihelper://install-plugin?url=http://example.com/{}", name)).is_err());
+        assert!(parse("aihelper://install-plugin?url=https://example.com/readme.txt").is_err());
+        assert!(parse("aihelper://install-plugin?url=https://example.com/").is_err());
+    }
+}
@@ -0,0 +1,485 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, UNIX_EPOCH},
+};
+
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use tauri::{async_runtime::Mutex, Manager as _, State};
+
+use crate::{
+    embedding::{self, Embedder},
+    paths::cache_dir,
+    serde_obj::ConfigFile,
+    tokenizer::{MessageType, SystemMessage},
+};
+
+const EMBED_BATCH: usize = 16;
+const MAX_FILES: usize = 20_000;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS files (
+    path TEXT PRIMARY KEY,
+    modified INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    model TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS chunks (
+    path TEXT NOT NULL,
+    start_line INTEGER NOT NULL,
+    end_line INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    vector BLOB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS chunks_path ON chunks (path);
+";
+
+// only one indexing run at a time, the startup run and a manual one can overlap
+static INDEXING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DocumentsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub folders: Vec<String>,
+    #[serde(default = "default_extensions")]
+    pub extensions: Vec<String>,
+    #[serde(default = "default_chunk_chars")]
+    pub chunk_chars: usize,
+    // text repeated at the start of the next chunk so a sentence is never only cut in half
+    #[serde(default = "default_chunk_overlap")]
+    pub chunk_overlap: usize,
+    #[serde(default = "default_top_k")]
+    pub top_k: usize,
+    // chunks less similar than this are never sent to the model
+    #[serde(default = "default_min_score")]
+    pub min_score: f32,
+    #[serde(default = "default_max_file_kb")]
+    pub max_file_kb: u64,
+}
+
+impl DocumentsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(folder) = self.folders.iter().find(|f| !Path::new(f).is_dir()) {
+            return Err(format!("folder {} not found", folder));
+        }
+        if self.chunk_chars == 0 {
+            return Err("chunk_chars must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn default_extensions() -> Vec<String> {
+    ["md", "txt", "rst", "org", "adoc", "html", "json", "yaml", "yml", "toml", "csv"]
+        .iter()
+        .map(|e| e.to_string())
+        .collect()
+}
+
+fn default_chunk_chars() -> usize {
+    1000
+}
+
+fn default_chunk_overlap() -> usize {
+    200
+}
+
+fn default_top_k() -> usize {
+    4
+}
+
+fn default_min_score() -> f32 {
+    0.3
+}
+
+fn default_max_file_kb() -> u64 {
+    512
+}
+
+impl Default for DocumentsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            folders: Vec::new(),
+            extensions: default_extensions(),
+            chunk_chars: default_chunk_chars(),
+            chunk_overlap: default_chunk_overlap(),
+            top_k: default_top_k(),
+            min_score: default_min_score(),
+            max_file_kb: default_max_file_kb(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chunk {
+    pub start_line: usize,
+    pub end_line: usize,
+    pub content: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DocumentHit {
+    pub path: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub content: String,
+    pub score: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct IndexStats {
+    pub files: usize,
+    pub indexed: usize,
+    pub removed: usize,
+    pub chunks: usize,
+    pub skipped: usize,
+}
+
+fn err(e: rusqlite::Error) -> String {
+    e.to_string()
+}
+
+// lines that are longer than a whole chunk are cut so no chunk grows without limit
+fn split_long_lines(text: &str, max_chars: usize) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        if chars.len() <= max_chars {
+            lines.push((idx + 1, line.to_string()));
+            continue;
+        }
+        for piece in chars.chunks(max_chars) {
+            lines.push((idx + 1, piece.iter().collect()));
+        }
+    }
+    lines
+}
+
+// chunks follow line boundaries so citations can point at line numbers
+pub fn chunk_text(text: &str, chunk_chars: usize, overlap: usize) -> Vec<Chunk> {
+    let chunk_chars = chunk_chars.max(1);
+    let lines = split_long_lines(text, chunk_chars);
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let mut end = start;
+        let mut size = 0;
+        while end < lines.len() && (end == start || size + lines[end].1.chars().count() < chunk_chars) {
+            size += lines[end].1.chars().count() + 1;
+            end += 1;
+        }
+        let content = lines[start..end]
+            .iter()
+            .map(|(_, line)| line.as_str())
+            .collect::<Vec<&str>>()
+            .join("\n");
+        if !content.trim().is_empty() {
+            chunks.push(Chunk {
+                start_line: lines[start].0,
+                end_line: lines[end - 1].0,
+                content,
+            });
+        }
+        if end >= lines.len() {
+            break;
+        }
+        // step back over the last lines that fit into the overlap, always moving forward
+        let mut next = end;
+        let mut kept = 0;
+        while next > start + 1 && kept + lines[next - 1].1.chars().count() < overlap {
+            kept += lines[next - 1].1.chars().count() + 1;
+            next -= 1;
+        }
+        start = next;
+    }
+    chunks
+}
+
+fn collect_files(dir: &Path, config: &DocumentsConfig, out: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        eprintln!("read document folder {} error", dir.display());
+        return;
+    };
+    for entry in entries.flatten() {
+        if out.len() >= MAX_FILES {
+            return;
+        }
+        let path = entry.path();
+        // .git, .venv and friends are never documents
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let Ok(file_type) = entry.file_type() else { continue };
+        if file_type.is_dir() {
+            collect_files(&path, config, out);
+            continue;
+        }
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if file_type.is_file() && config.extensions.iter().any(|e| e.eq_ignore_ascii_case(&extension)) {
+            out.push(path);
+        }
+    }
+}
+
+pub fn open_index(path: &Path) -> Result<Connection, String> {
+    let conn = Connection::open(path).map_err(err)?;
+    conn.busy_timeout(Duration::from_secs(5)).map_err(err)?;
+    conn.execute_batch(SCHEMA).map_err(err)?;
+    Ok(conn)
+}
+
+fn modified(meta: &std::fs::Metadata) -> i64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+fn index_file(
+    conn: &mut Connection,
+    path: &str,
+    text: &str,
+    config: &DocumentsConfig,
+    embedder: &dyn Embedder,
+) -> Result<usize, String> {
+    let chunks = chunk_text(text, config.chunk_chars, config.chunk_overlap);
+    let mut vectors = Vec::with_capacity(chunks.len());
+    for batch in chunks.chunks(EMBED_BATCH) {
+        let texts: Vec<String> = batch.iter().map(|c| c.content.clone()).collect();
+        vectors.extend(embedder.embed(&texts)?);
+    }
+    let tx = conn.transaction().map_err(err)?;
+    tx.execute("DELETE FROM chunks WHERE path = ?1", params![path]).map_err(err)?;
+    for (chunk, vector) in chunks.iter().zip(vectors) {
+        tx.execute(
+            "INSERT INTO chunks (path, start_line, end_line, content, vector) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![path, chunk.start_line as i64, chunk.end_line as i64, chunk.content, embedding::to_bytes(&vector)],
+        )
+        .map_err(err)?;
+    }
+    tx.commit().map_err(err)?;
+    Ok(chunks.len())
+}
+
+// files are indexed again only when their size, time or the embedding model changed
+pub fn index_folders(conn: &mut Connection, config: &DocumentsConfig, embedder: &dyn Embedder) -> Result<IndexStats, String> {
+    let mut files = Vec::new();
+    for folder in &config.folders {
+        collect_files(Path::new(folder), config, &mut files);
+    }
+    let known: HashMap<String, (i64, i64, String)> = {
+        let mut stmt = conn.prepare("SELECT path, modified, size, model FROM files").map_err(err)?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?, row.get(3)?))))
+            .map_err(err)?;
+        rows.collect::<Result<_, _>>().map_err(err)?
+    };
+    let mut stats = IndexStats::default();
+    let mut seen = HashSet::new();
+    for file in files {
+        let Ok(meta) = std::fs::metadata(&file) else { continue };
+        let path = file.to_string_lossy().to_string();
+        if meta.len() > config.max_file_kb * 1024 {
+            stats.skipped += 1;
+            continue;
+        }
+        seen.insert(path.clone());
+        stats.files += 1;
+        let state = (modified(&meta), meta.len() as i64, embedder.model().to_string());
+        if known.get(&path) == Some(&state) {
+            continue;
+        }
+        // binary files and other encodings are left out
+        let Ok(text) = std::fs::read_to_string(&file) else {
+            stats.skipped += 1;
+            continue;
+        };
+        stats.chunks += index_file(conn, &path, &text, config, embedder)?;
+        conn.execute(
+            "INSERT OR REPLACE INTO files (path, modified, size, model) VALUES (?1, ?2, ?3, ?4)",
+            params![path, state.0, state.1, state.2],
+        )
+        .map_err(err)?;
+        stats.indexed += 1;
+    }
+    let tx = conn.transaction().map_err(err)?;
+    for path in known.keys().filter(|p| !seen.contains(*p)) {
+        tx.execute("DELETE FROM chunks WHERE path = ?1", params![path]).map_err(err)?;
+        tx.execute("DELETE FROM files WHERE path = ?1", params![path]).map_err(err)?;
+        stats.removed += 1;
+    }
+    tx.commit().map_err(err)?;
+    Ok(stats)
+}
+
+pub fn retrieve(
+    conn: &Connection,
+    embedder: &dyn Embedder,
+    query: &str,
+    top_k: usize,
+    min_score: f32,
+) -> Result<Vec<DocumentHit>, String> {
+    if query.trim().is_empty() || top_k == 0 {
+        return Ok(Vec::new());
+    }
+    let target = embedder
+        .embed(&[query.to_string()])?
+        .pop()
+        .ok_or("embedding model returned nothing")?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT chunks.path, chunks.start_line, chunks.end_line, chunks.content, chunks.vector
+             FROM chunks JOIN files ON files.path = chunks.path WHERE files.model = ?1",
+        )
+        .map_err(err)?;
+    let rows = stmt
+        .query_map(params![embedder.model()], |row| {
+            let vector: Vec<u8> = row.get(4)?;
+            Ok(DocumentHit {
+                path: row.get(0)?,
+                start_line: row.get::<_, i64>(1)? as usize,
+                end_line: row.get::<_, i64>(2)? as usize,
+                content: row.get(3)?,
+                score: embedding::similarity(&target, &embedding::from_bytes(&vector)),
+            })
+        })
+        .map_err(err)?;
+    let mut hits = Vec::new();
+    for hit in rows {
+        let hit = hit.map_err(err)?;
+        if hit.score >= min_score {
+            hits.push(hit);
+        }
+    }
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hits.truncate(top_k);
+    Ok(hits)
+}
+
+// numbered so the model can cite them as [n]
+pub fn format_context(hits: &[DocumentHit]) -> String {
+    let mut context = String::from(
+        "Excerpts from the user's local documents that may help with the last message. \
+Use them only if they are relevant and cite every excerpt you use as [n] with its file path.",
+    );
+    for (idx, hit) in hits.iter().enumerate() {
+        context.push_str(&format!(
+            "\n\n[{}] {} (lines {}-{})\n{}",
+            idx + 1,
+            hit.path,
+            hit.start_line,
+            hit.end_line,
+            hit.content
+        ));
+    }
+    context
+}
+
+pub fn get_index_path() -> PathBuf {
+    cache_dir().join("documents.db")
+}
+
+fn embedder_state(app: &tauri::AppHandle) -> Option<Arc<dyn Embedder>> {
+    let embedder: State<Option<Arc<dyn Embedder>>> = app.state();
+    embedder.inner().clone()
+}
+
+pub async fn reindex(app: &tauri::AppHandle) -> Result<IndexStats, String> {
+    let config_s: State<Arc<Mutex<ConfigFile>>> = app.state();
+    let config = config_s.lock().await.documents.clone();
+    let embedder = embedder_state(app).ok_or("documents need embedding.model_path in the config")?;
+    if INDEXING.swap(true, Ordering::SeqCst) {
+        return Err("documents are already being indexed".to_string());
+    }
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let mut conn = open_index(&get_index_path())?;
+        index_folders(&mut conn, &config, embedder.as_ref())
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|r| r);
+    INDEXING.store(false, Ordering::SeqCst);
+    if let Ok(stats) = &result {
+        let _ = app.emit_all("documents-indexed", stats.clone());
+    }
+    result
+}
+
+// the excerpts for the last user message, None when retrieval is off or nothing matched
+pub async fn retrieve_context(app: &tauri::AppHandle, config: &DocumentsConfig, messages: &[MessageType]) -> Option<SystemMessage> {
+    if !config.enabled || config.folders.is_empty() {
+        return None;
+    }
+    let query = messages.iter().rev().find_map(|m| match m {
+        MessageType::User(m) => Some(m.content.clone()),
+        _ => None,
+    })?;
+    let embedder = embedder_state(app)?;
+    let (top_k, min_score) = (config.top_k, config.min_score);
+    let hits = tauri::async_runtime::spawn_blocking(move || {
+        let conn = open_index(&get_index_path())?;
+        retrieve(&conn, embedder.as_ref(), &query, top_k, min_score)
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|r| r);
+    match hits {
+        Ok(hits) if !hits.is_empty() => Some(SystemMessage { content: format_context(&hits) }),
+        Ok(_) => None,
+        Err(e) => {
+            eprintln!("retrieve documents error {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(chunks: &[Chunk]) -> Vec<(usize, usize, &str)> {
+        chunks.iter().map(|c| (c.start_line, c.end_line, c.content.as_str())).collect()
+    }
+
+    #[test]
+    fn chunks_end_on_line_boundaries() {
+        let chunks = chunk_text("aaaa\nbbbb\ncccc\ndddd", 10, 0);
+        assert_eq!(spans(&chunks), vec![(1, 2, "aaaa\nbbbb"), (3, 4, "cccc\ndddd")]);
+    }
+
+    #[test]
+    fn overlap_repeats_the_last_lines() {
+        let chunks = chunk_text("aaaa\nbbbb\ncccc\ndddd", 10, 5);
+        assert_eq!(
+            spans(&chunks),
+            vec![(1, 2, "aaaa\nbbbb"), (2, 3, "bbbb\ncccc"), (3, 4, "cccc\ndddd")]
+        );
+        // an overlap as big as the chunk still moves forward
+        assert_eq!(chunk_text("aaaa\nbbbb\ncccc", 10, 100).len(), 2);
+    }
+
+    #[test]
+    fn long_multibyte_lines_are_cut_on_characters() {
+        let chunks = chunk_text("ééééé\n日本語", 2, 0);
+        assert_eq!(spans(&chunks), vec![(1, 1, "éé"), (1, 1, "éé"), (1, 1, "é"), (2, 2, "日本"), (2, 2, "語")]);
+    }
+
+    #[test]
+    fn blank_text_has_no_chunks() {
+        assert!(chunk_text("", 10, 2).is_empty());
+        assert!(chunk_text("\n   \n", 10, 2).is_empty());
+        assert_eq!(spans(&chunk_text("\n\nhello", 100, 0)), vec![(1, 3, "\n\nhello")]);
+    }
+}
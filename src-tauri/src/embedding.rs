@@ -0,0 +1,122 @@
+use std::{path::Path, sync::Arc};
+
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config};
+use serde::{Deserialize, Serialize};
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+
+// sentence-transformers models are trained on short passages, longer input is cut
+const MAX_INPUT_TOKENS: usize = 256;
+
+pub trait Embedder: Send + Sync {
+    // stored next to every vector so switching models never mixes them
+    fn model(&self) -> &str;
+    // one normalized vector per text
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, String>;
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct EmbeddingConfig {
+    // folder of a bert style sentence-transformers model with config.json, tokenizer.json
+    // and model.safetensors, e.g. all-MiniLM-L6-v2. semantic search is off when empty
+    #[serde(default)]
+    pub model_path: Option<String>,
+}
+
+pub struct BertEmbedder {
+    name: String,
+    model: BertModel,
+    tokenizer: Tokenizer,
+    device: Device,
+}
+
+impl BertEmbedder {
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let device = Device::Cpu;
+        let config = std::fs::read_to_string(dir.join("config.json")).map_err(|e| e.to_string())?;
+        let config: Config = serde_json::from_str(&config).map_err(|e| e.to_string())?;
+        let mut tokenizer = Tokenizer::from_file(dir.join("tokenizer.json")).map_err(|e| e.to_string())?;
+        tokenizer.with_padding(Some(PaddingParams::default()));
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: MAX_INPUT_TOKENS,
+                ..Default::default()
+            }))
+            .map_err(|e| e.to_string())?;
+        let weights = dir.join("model.safetensors");
+        // the file is only read while it is mapped, candle copies the tensors to memory
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], DType::F32, &device) }
+            .map_err(|e| e.to_string())?;
+        let model = BertModel::load(vb, &config).map_err(|e| e.to_string())?;
+        let name = dir
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| "bert".to_string());
+        Ok(Self { name, model, tokenizer, device })
+    }
+
+    fn forward(&self, texts: &[String]) -> candle_core::Result<Vec<Vec<f32>>> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts.to_vec(), true)
+            .map_err(candle_core::Error::msg)?;
+        let rows = |f: fn(&tokenizers::Encoding) -> &[u32]| -> candle_core::Result<Tensor> {
+            let rows = encodings
+                .iter()
+                .map(|e| Tensor::new(f(e), &self.device))
+                .collect::<candle_core::Result<Vec<Tensor>>>()?;
+            Tensor::stack(&rows, 0)
+        };
+        let ids = rows(|e| e.get_ids())?;
+        let type_ids = rows(|e| e.get_type_ids())?;
+        let mask = rows(|e| e.get_attention_mask())?;
+        let hidden = self.model.forward(&ids, &type_ids, Some(&mask))?;
+        // mean over the real tokens, padding is masked out
+        let mask = mask.to_dtype(DType::F32)?.unsqueeze(2)?;
+        let summed = hidden.broadcast_mul(&mask)?.sum(1)?;
+        let pooled = summed.broadcast_div(&mask.sum(1)?)?;
+        let norm = pooled.sqr()?.sum_keepdim(1)?.sqrt()?;
+        pooled.broadcast_div(&norm)?.to_vec2::<f32>()
+    }
+}
+
+impl Embedder for BertEmbedder {
+    fn model(&self) -> &str {
+        &self.name
+    }
+
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.forward(texts).map_err(|e| e.to_string())
+    }
+}
+
+pub fn load_embedder(config: &EmbeddingConfig) -> Option<Arc<dyn Embedder>> {
+    let path = config.model_path.as_ref()?;
+    match BertEmbedder::load(Path::new(path)) {
+        Ok(embedder) => Some(Arc::new(embedder)),
+        Err(e) => {
+            eprintln!("load embedding model {} error {}", path, e);
+            None
+        }
+    }
+}
+
+// vectors are normalized so the dot product is the cosine similarity
+pub fn similarity(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+pub fn to_bytes(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+pub fn from_bytes(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
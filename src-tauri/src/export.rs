@@ -0,0 +1,366 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    conversation::Conversation,
+    paths::data_dir,
+    serde_obj::ToolCallFn,
+    tokenizer::MessageType,
+};
+
+pub const EXPORT_FORMAT: &str = "rasast-conversation";
+pub const EXPORT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    Html,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Json => "json",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+// the json export keeps the whole tree so it can be imported again without losing branches
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ConversationExport {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: i64,
+    pub model: String,
+    pub conversation: Conversation,
+}
+
+// nodes written before times were kept have 0, they get no time instead of 1970
+fn format_time(timestamp: i64) -> Option<String> {
+    if timestamp <= 0 {
+        return None;
+    }
+    let time = chrono::DateTime::from_timestamp(timestamp, 0)?;
+    Some(time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+}
+
+fn with_time(label: &str, timestamp: i64) -> String {
+    match format_time(timestamp) {
+        Some(time) => format!("{} · {}", label, time),
+        None => label.to_string(),
+    }
+}
+
+// a fence longer than any backtick run inside the content so it cannot be closed early
+fn fence(content: &str, lang: &str) -> String {
+    let mut longest = 0;
+    let mut run = 0;
+    for c in content.chars() {
+        run = if c == '`' { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    let ticks = "`".repeat(longest.max(2) + 1);
+    format!("{ticks}{lang}\n{content}\n{ticks}\n")
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn conversation_title(conversation: &Conversation) -> String {
+    if let Some(title) = conversation.title.as_ref().filter(|t| !t.trim().is_empty()) {
+        return title.clone();
+    }
+    let first_user = conversation.messages().into_iter().find_map(|m| match m {
+        MessageType::User(m) => Some(m.content),
+        _ => None,
+    });
+    let title = first_user.unwrap_or_else(|| "Conversation".to_string());
+    let title = title.lines().next().unwrap_or("").trim().to_string();
+    if title.chars().count() > 60 {
+        format!("{}…", title.chars().take(60).collect::<String>())
+    } else {
+        title
+    }
+}
+
+// models recorded on the replies, older chats fall back to the model of their profile
+fn conversation_models(conversation: &Conversation, profile_model: &str) -> String {
+    let mut models: Vec<String> = Vec::new();
+    for node in &conversation.nodes {
+        if let MessageType::Assistant(m) = &node.message {
+            if let Some(model) = m.model.as_ref().filter(|m| !models.contains(m)) {
+                models.push(model.clone());
+            }
+        }
+    }
+    if models.is_empty() {
+        return profile_model.to_string();
+    }
+    models.join(", ")
+}
+
+pub fn to_markdown(conversation: &Conversation, profile_model: &str) -> String {
+    let mut md = format!("# {}\n\n", conversation_title(conversation));
+    if let Some(created) = format_time(conversation.created_at) {
+        md.push_str(&format!("- Created: {}\n", created));
+    }
+    if let Some(updated) = format_time(conversation.updated_at) {
+        md.push_str(&format!("- Updated: {}\n", updated));
+    }
+    md.push_str(&format!("- Model: {}\n", conversation_models(conversation, profile_model)));
+    if let Some(persona) = &conversation.persona {
+        md.push_str(&format!("- Persona: {}\n", persona));
+    }
+    md.push_str(&format!("- Id: `{}`\n\n---\n\n", conversation.id));
+    let summary = conversation.active_summary();
+    for (idx, node) in conversation.active_path().into_iter().enumerate() {
+        if let Some(summary) = summary.filter(|s| s.covered == idx) {
+            md.push_str("> **Summary of earlier messages**\n>\n");
+            for line in summary.content.lines() {
+                md.push_str(&format!("> {}\n", line));
+            }
+            md.push('\n');
+        }
+        let node = &conversation.nodes[node];
+        let time = node.created_at;
+        match &node.message {
+            MessageType::User(m) => {
+                md.push_str(&format!("### {}\n\n{}\n\n", with_time("User", time), m.content));
+                for attachment in &m.attachments {
+                    md.push_str(&format!("📎 {}\n\n", attachment.name));
+                }
+            }
+            MessageType::Assistant(m) => {
+                md.push_str(&format!("### {}\n\n{}\n\n", with_time("Assistant", time), m.content))
+            }
+            MessageType::System(m) => md.push_str(&format!("### {}\n\n{}\n\n", with_time("System", time), m.content)),
+            MessageType::ToolCall(m) => {
+                let tool_calls: Vec<ToolCallFn> = serde_json::from_str(&m.content).unwrap_or_default();
+                for tool_call in tool_calls {
+                    let arguments = serde_json::to_string_pretty(&tool_call.arguments).unwrap_or_default();
+                    let label = format!("Tool call `{}`", tool_call.name);
+                    md.push_str(&format!("#### {}\n\n", with_time(&label, time)));
+                    md.push_str(&fence(&arguments, "json"));
+                    md.push('\n');
+                }
+            }
+            MessageType::ToolResponse(m) => {
+                let result = match &m.content {
+                    serde_json::Value::String(s) => s.clone(),
+                    other => serde_json::to_string_pretty(other).unwrap_or_default(),
+                };
+                md.push_str(&format!(
+                    "#### Tool result `{}` · {:?} · {} ms\n\n",
+                    m.name, m.status, m.duration_ms
+                ));
+                md.push_str(&fence(&result, ""));
+                md.push('\n');
+            }
+        }
+    }
+    md
+}
+
+pub fn to_json(conversation: &Conversation, profile_model: &str) -> String {
+    let export = ConversationExport {
+        format: EXPORT_FORMAT.to_string(),
+        version: EXPORT_VERSION,
+        exported_at: chrono::Utc::now().timestamp(),
+        model: conversation_models(conversation, profile_model),
+        conversation: conversation.clone(),
+    };
+    serde_json::to_string_pretty(&export).unwrap()
+}
+
+pub fn to_html(conversation: &Conversation, profile_model: &str) -> String {
+    let body = markdown::to_html(&to_markdown(conversation, profile_model));
+    format!(
+        r#"<!doctype html>
+<html lang="en">
+<head>
+<meta charset="utf-8" />
+<meta name="viewport" content="width=device-width, initial-scale=1" />
+<title>{}</title>
+<style>
+body {{ max-width: 860px; margin: 40px auto; padding: 0 20px; font-family: Ubuntu, sans-serif; line-height: 1.5; color: #1f1f1f; }}
+pre {{ background: #f4f4f4; padding: 10px; border-radius: 5px; overflow-x: auto; }}
+blockquote {{ border-left: 4px solid #708fff; margin: 0; padding-left: 15px; color: #555; }}
+h3 {{ border-bottom: 1px solid #ddd; padding-bottom: 4px; }}
+</style>
+</head>
+<body>
+{}
+</body>
+</html>
+"#,
+        escape_html(&conversation_title(conversation)),
+        body
+    )
+}
+
+pub fn export_conversation(
+    conversation: &Conversation,
+    profile_model: &str,
+    format: ExportFormat,
+    path: Option<PathBuf>,
+) -> Result<PathBuf, String> {
+    let content = match format {
+        ExportFormat::Markdown => to_markdown(conversation, profile_model),
+        ExportFormat::Json => to_json(conversation, profile_model),
+        ExportFormat::Html => to_html(conversation, profile_model),
+    };
+    let path = match path {
+        Some(path) => path,
+        None => {
+            let dir = data_dir().join("exports");
+            std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+            dir.join(format!("{}.{}", conversation.id, format.extension()))
+        }
+    };
+    std::fs::write(&path, content).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        serde_obj::ToolStatus,
+        tokenizer::{AssistantMessage, ToolCall, ToolResponse, UserMessage},
+    };
+
+    // zero times keep the snapshots independent of the local timezone
+    fn sample() -> Conversation {
+        let mut conversation = Conversation::default();
+        conversation.id = "c1".to_string();
+        conversation.created_at = 0;
+        conversation.updated_at = 0;
+        conversation.push(MessageType::User(UserMessage::new("What is 2+2?".to_string())));
+        conversation.push(MessageType::ToolCall(ToolCall {
+            content: r#"[{"name": "calculate", "arguments": {"expression": "2+2"}, "call_id": "a"}]"#.to_string(),
+        }));
+        conversation.push(MessageType::ToolResponse(ToolResponse {
+            content: serde_json::json!("4"),
+            call_id: Some("a".to_string()),
+            name: "calculate".to_string(),
+            status: ToolStatus::Success,
+            duration_ms: 3,
+        }));
+        conversation.push(MessageType::Assistant(AssistantMessage { content: "It is 4.".to_string(), model: None }));
+        for node in &mut conversation.nodes {
+            node.created_at = 0;
+        }
+        conversation
+    }
+
+    #[test]
+    fn fence_outlasts_backticks_in_content() {
+        assert_eq!(fence("a ```` b", "txt"), "`````txt\na ```` b\n`````\n");
+        assert_eq!(fence("plain", ""), "```\nplain\n```\n");
+    }
+
+    #[test]
+    fn markdown_snapshot() {
+        let expected = "\
+# What is 2+2?
+
+- Model: mistral-7b-instruct-v0.3
+- Id: `c1`
+
+---
+
+### User
+
+What is 2+2?
+
+#### Tool call `calculate`
+
+```json
+{
+  \"expression\": \"2+2\"
+}
+```
+
+#### Tool result `calculate` · Success · 3 ms
+
+```
+4
+```
+
+### Assistant
+
+It is 4.
+
+";
+        assert_eq!(to_markdown(&sample(), "mistral-7b-instruct-v0.3"), expected);
+    }
+
+    #[test]
+    fn recorded_models_win_over_the_profile_model() {
+        let mut conversation = sample();
+        conversation.push(MessageType::Assistant(AssistantMessage {
+            content: "Again 4.".to_string(),
+            model: Some("llama3".to_string()),
+        }));
+        assert!(to_markdown(&conversation, "fallback").contains("- Model: llama3\n"));
+    }
+
+    #[test]
+    fn json_snapshot() {
+        let mut export: serde_json::Value = serde_json::from_str(&to_json(&sample(), "mistral")).unwrap();
+        export["exported_at"] = serde_json::json!(0);
+        let expected = serde_json::json!({
+            "format": "rasast-conversation",
+            "version": 1,
+            "exported_at": 0,
+            "model": "mistral",
+            "conversation": {
+                "id": "c1",
+                "title": null,
+                "created_at": 0,
+                "updated_at": 0,
+                "persona": null,
+                "params": null,
+                "profile": null,
+                "nodes": [
+                    {"parent": null, "message": {"role": "user", "content": "What is 2+2?"}, "active_child": 1, "created_at": 0},
+                    {
+                        "parent": 0,
+                        "message": {
+                            "role": "tool_call",
+                            "content": r#"[{"name": "calculate", "arguments": {"expression": "2+2"}, "call_id": "a"}]"#
+                        },
+                        "active_child": 2,
+                        "created_at": 0
+                    },
+                    {
+                        "parent": 1,
+                        "message": {
+                            "role": "tool_response",
+                            "content": "4",
+                            "call_id": "a",
+                            "name": "calculate",
+                            "status": "success",
+                            "duration_ms": 3
+                        },
+                        "active_child": 3,
+                        "created_at": 0
+                    },
+                    {"parent": 2, "message": {"role": "assistant", "content": "It is 4."}, "active_child": null, "created_at": 0}
+                ],
+                "active_root": 0,
+                "summary": null
+            }
+        });
+        assert_eq!(export, expected);
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tauri::{async_runtime::Mutex, Manager as _, State};
+
+use crate::{conversation::Conversation, serde_obj::ConfigFile};
+
+// None means "let the backend decide"
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct GenerationParams {
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    #[serde(default)]
+    pub stop: Vec<String>,
+}
+
+impl GenerationParams {
+    // values set in `over` win, used for per conversation overrides
+    pub fn merged(&self, over: &GenerationParams) -> GenerationParams {
+        GenerationParams {
+            temperature: over.temperature.or(self.temperature),
+            top_p: over.top_p.or(self.top_p),
+            max_tokens: over.max_tokens.or(self.max_tokens),
+            stop: if over.stop.is_empty() { self.stop.clone() } else { over.stop.clone() },
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(temperature) = self.temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                return Err("temperature must be between 0 and 2".to_string());
+            }
+        }
+        if let Some(top_p) = self.top_p {
+            if top_p <= 0.0 || top_p > 1.0 {
+                return Err("top_p must be greater than 0 and at most 1".to_string());
+            }
+        }
+        if self.max_tokens == Some(0) {
+            return Err("max_tokens must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    // the index where the text has to be cut because of a stop sequence
+    pub fn find_stop(&self, text: &str) -> Option<usize> {
+        self.stop
+            .iter()
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| text.find(s.as_str()))
+            .min()
+    }
+}
+
+// config, then the model profile, then the conversation overrides
+pub async fn current_params(app: &tauri::AppHandle, profile: &GenerationParams) -> GenerationParams {
+    let config: State<Arc<Mutex<ConfigFile>>> = app.state();
+    let params = config.lock().await.generation.merged(profile);
+    let conversation: State<Arc<Mutex<Conversation>>> = app.state();
+    match &conversation.lock().await.params {
+        Some(over) => params.merged(over),
+        None => params,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_values_win_over_global_ones() {
+        let global = GenerationParams {
+            temperature: Some(0.7),
+            top_p: Some(0.9),
+            max_tokens: None,
+            stop: vec!["</s>".to_string()],
+        };
+        let profile = GenerationParams { temperature: Some(0.2), max_tokens: Some(256), ..Default::default() };
+        let merged = global.merged(&profile);
+        assert_eq!(merged.temperature, Some(0.2));
+        assert_eq!(merged.top_p, Some(0.9));
+        assert_eq!(merged.max_tokens, Some(256));
+        // an empty list keeps the stops from below, a set list replaces them
+        assert_eq!(merged.stop, vec!["</s>".to_string()]);
+        let over = GenerationParams { stop: vec!["END".to_string()], ..Default::default() };
+        assert_eq!(merged.merged(&over).stop, vec!["END".to_string()]);
+    }
+
+    #[test]
+    fn values_out_of_range_are_rejected() {
+        assert!(GenerationParams::default().validate().is_ok());
+        let edges = GenerationParams { temperature: Some(2.0), top_p: Some(1.0), max_tokens: Some(1), stop: vec![] };
+        assert!(edges.validate().is_ok());
+        for params in [
+            GenerationParams { temperature: Some(-0.1), ..Default::default() },
+            GenerationParams { temperature: Some(2.5), ..Default::default() },
+            GenerationParams { top_p: Some(0.0), ..Default::default() },
+            GenerationParams { top_p: Some(1.5), ..Default::default() },
+            GenerationParams { max_tokens: Some(0), ..Default::default() },
+        ] {
+            assert!(params.validate().is_err(), "{:?}", params);
+        }
+    }
+
+    #[test]
+    fn stop_split_over_chunks_is_found_once_complete() {
+        let params = GenerationParams {
+            stop: vec!["".to_string(), "###".to_string(), "\nUser:".to_string()],
+            ..Default::default()
+        };
+        let mut text = String::new();
+        let mut found = None;
+        for chunk in ["The answer", " is 4.#", "#", "# more", "\nUser: hi"] {
+            text.push_str(chunk);
+            found = params.find_stop(&text);
+            if found.is_some() {
+                break;
+            }
+        }
+        assert_eq!(found, Some("The answer is 4.".len()));
+        // the earliest stop wins whichever comes first in the list
+        assert_eq!(params.find_stop("a\nUser: b ### c"), Some(1));
+        assert_eq!(params.find_stop("no stop here"), None);
+    }
+}
@@ -0,0 +1,215 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use tauri::{async_runtime::Mutex, GlobalShortcutManager as _, Manager as _, State};
+
+use crate::{
+    conversation::{save_current, Conversation},
+    quick_action::{self, QuickAction},
+    voice,
+};
+
+pub const TOGGLE_WINDOW: &str = "toggle_window";
+pub const NEW_CHAT: &str = "new_chat";
+pub const PUSH_TO_TALK: &str = "push_to_talk";
+pub const QUICK_ACTION_PREFIX: &str = "quick_action:";
+
+// action -> accelerator, e.g. "toggle_window": "CmdOrCtrl+Alt+Space"
+pub type Keymap = BTreeMap<String, String>;
+
+pub fn default_hotkeys() -> Keymap {
+    Keymap::from([(TOGGLE_WINDOW.to_string(), "CmdOrCtrl+Alt+Space".to_string())])
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HotkeyAction {
+    pub id: String,
+    pub label: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HotkeyError {
+    pub action: String,
+    pub shortcut: String,
+    pub error: String,
+}
+
+pub fn available_actions(quick_actions: &[QuickAction]) -> Vec<HotkeyAction> {
+    let mut actions = vec![
+        HotkeyAction { id: TOGGLE_WINDOW.to_string(), label: "Show / hide the assistant".to_string() },
+        HotkeyAction { id: NEW_CHAT.to_string(), label: "New chat".to_string() },
+        HotkeyAction { id: PUSH_TO_TALK.to_string(), label: "Voice input (press again to send)".to_string() },
+    ];
+    for action in quick_actions {
+        actions.push(HotkeyAction {
+            id: format!("{}{}", QUICK_ACTION_PREFIX, action.id),
+            label: format!("Quick action: {}", action.label),
+        });
+    }
+    actions
+}
+
+fn normalize_part(part: &str) -> String {
+    let part = part.trim().to_lowercase();
+    match part.as_str() {
+        "control" => "ctrl".to_string(),
+        "cmdorctrl" | "commandorcontrol" => "cmdorctrl".to_string(),
+        "command" | "cmd" | "super" | "meta" => "super".to_string(),
+        "option" => "alt".to_string(),
+        _ => part,
+    }
+}
+
+// "Ctrl+Shift+K" and "shift+control+k" are the same shortcut
+pub fn normalize(shortcut: &str) -> String {
+    let mut parts: Vec<String> = shortcut.split('+').map(normalize_part).collect();
+    let key = parts.pop().unwrap_or_default();
+    parts.sort();
+    parts.dedup();
+    parts.push(key);
+    parts.join("+")
+}
+
+// unknown actions and two actions on the same keys are errors before anything is registered
+pub fn validate(keymap: &Keymap, quick_actions: &[QuickAction]) -> Result<(), String> {
+    let actions = available_actions(quick_actions);
+    let mut seen: BTreeMap<String, &str> = BTreeMap::new();
+    for (action, shortcut) in keymap {
+        if !actions.iter().any(|a| &a.id == action) {
+            return Err(format!("unknown hotkey action {}", action));
+        }
+        if shortcut.trim().is_empty() {
+            continue;
+        }
+        if let Some(other) = seen.insert(normalize(shortcut), action) {
+            return Err(format!("{} is used by both {} and {}", shortcut, other, action));
+        }
+    }
+    Ok(())
+}
+
+fn toggle_window(app: &tauri::AppHandle) {
+    if let Some(window) = app.get_window("main") {
+        if window.is_visible().unwrap_or(false) && window.is_focused().unwrap_or(false) {
+            let _ = window.hide();
+            return;
+        }
+    }
+    crate::create_main_window(app.clone());
+}
+
+pub async fn new_chat(app: &tauri::AppHandle) {
+    save_current(app).await;
+    let conversation: State<Arc<Mutex<Conversation>>> = app.state();
+    *conversation.lock().await = Conversation::default();
+    let _ = app.emit_all("conversation-changed", ());
+}
+
+fn run_action(app: &tauri::AppHandle, action: &str) {
+    match action {
+        TOGGLE_WINDOW => toggle_window(app),
+        NEW_CHAT => {
+            let app = app.clone();
+            tauri::async_runtime::spawn(async move {
+                new_chat(&app).await;
+                crate::create_main_window(app);
+            });
+        }
+        PUSH_TO_TALK => {
+            let app = app.clone();
+            tauri::async_runtime::spawn(async move { voice::toggle(&app).await });
+        }
+        _ => {
+            let Some(id) = action.strip_prefix(QUICK_ACTION_PREFIX) else { return };
+            let app = app.clone();
+            let id = id.to_string();
+            tauri::async_runtime::spawn(async move {
+                if let Err(e) = quick_action::run_quick_action(app, id, None).await {
+                    eprintln!("quick action error {}", e);
+                }
+            });
+        }
+    }
+}
+
+// registers the whole keymap again, shortcuts another program already holds come back as errors
+pub fn apply(app: &tauri::AppHandle, keymap: &Keymap) -> Vec<HotkeyError> {
+    let mut manager = app.global_shortcut_manager();
+    if let Err(e) = manager.unregister_all() {
+        eprintln!("unregister hotkeys error {}", e);
+    }
+    let mut errors = Vec::new();
+    for (action, shortcut) in keymap {
+        if shortcut.trim().is_empty() {
+            continue;
+        }
+        let handler_app = app.clone();
+        let handler_action = action.clone();
+        let result = manager.register(shortcut, move || run_action(&handler_app, &handler_action));
+        if let Err(e) = result {
+            errors.push(HotkeyError {
+                action: action.clone(),
+                shortcut: shortcut.clone(),
+                error: e.to_string(),
+            });
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quick_actions() -> Vec<QuickAction> {
+        vec![QuickAction {
+            id: "explain".to_string(),
+            label: "Explain".to_string(),
+            prompt: "{{text}}".to_string(),
+            copy_result: false,
+        }]
+    }
+
+    #[test]
+    fn normalize_ignores_case_order_and_aliases() {
+        assert_eq!(normalize("Ctrl+Shift+K"), normalize("shift+control+k"));
+        assert_eq!(normalize("Command+Option+Space"), normalize("super+alt+space"));
+        assert_eq!(normalize("CommandOrControl+Alt+Space"), "alt+cmdorctrl+space");
+        assert_eq!(normalize("Ctrl+Control+K"), "ctrl+k");
+        // the key itself stays last even when it sorts first
+        assert_eq!(normalize("Shift+A"), "shift+a");
+        assert_ne!(normalize("Ctrl+K"), normalize("Ctrl+Shift+K"));
+    }
+
+    #[test]
+    fn validate_accepts_known_actions() {
+        let keymap = Keymap::from([
+            (TOGGLE_WINDOW.to_string(), "CmdOrCtrl+Alt+Space".to_string()),
+            ("quick_action:explain".to_string(), "CmdOrCtrl+Alt+E".to_string()),
+            (NEW_CHAT.to_string(), " ".to_string()),
+        ]);
+        assert_eq!(validate(&keymap, &quick_actions()), Ok(()));
+    }
+
+    #[test]
+    fn validate_rejects_conflicts_and_unknown_actions() {
+        let conflict = Keymap::from([
+            (TOGGLE_WINDOW.to_string(), "CmdOrCtrl+Alt+Space".to_string()),
+            (NEW_CHAT.to_string(), "alt+commandorcontrol+space".to_string()),
+        ]);
+        let error = validate(&conflict, &quick_actions()).unwrap_err();
+        assert!(error.contains(NEW_CHAT) && error.contains(TOGGLE_WINDOW), "{}", error);
+
+        let duplicate = Keymap::from([
+            (PUSH_TO_TALK.to_string(), "F8".to_string()),
+            ("quick_action:explain".to_string(), "f8".to_string()),
+        ]);
+        assert!(validate(&duplicate, &quick_actions()).is_err());
+
+        let removed_action = Keymap::from([("quick_action:translate".to_string(), "F9".to_string())]);
+        assert_eq!(
+            validate(&removed_action, &quick_actions()),
+            Err("unknown hotkey action quick_action:translate".to_string())
+        );
+    }
+}
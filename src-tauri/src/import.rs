@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::{
+    conversation::Conversation,
+    export::{ConversationExport, EXPORT_FORMAT},
+    serde_obj::{ToolCallFn, ToolStatus},
+    tokenizer::{AssistantMessage, MessageType, SystemMessage, ToolCall, ToolResponse, UserMessage},
+};
+
+// text from a plain string, a list of parts or an object with parts / text
+fn content_text(content: &Value) -> String {
+    match content {
+        Value::String(s) => s.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| match part {
+                Value::String(s) => Some(s.clone()),
+                Value::Object(o) if o.get("type").and_then(Value::as_str).unwrap_or("text") == "text" => {
+                    o.get("text").and_then(Value::as_str).map(str::to_string)
+                }
+                _ => None,
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
+        Value::Object(o) => {
+            if let Some(parts) = o.get("parts") {
+                content_text(parts)
+            } else {
+                o.get("text").and_then(Value::as_str).unwrap_or("").to_string()
+            }
+        }
+        _ => String::new(),
+    }
+}
+
+fn tool_call_message(name: &str, call_id: Option<String>, arguments: &Value) -> ToolCallFn {
+    let arguments = match arguments {
+        Value::Object(o) => o.clone().into_iter().collect(),
+        // openai sends arguments as a json string
+        Value::String(s) => match serde_json::from_str::<HashMap<String, Value>>(s) {
+            Ok(map) => map,
+            Err(_) => HashMap::from([("input".to_string(), Value::String(s.clone()))]),
+        },
+        _ => HashMap::new(),
+    };
+    ToolCallFn { name: name.to_string(), arguments, call_id }
+}
+
+fn tool_response(content: String, call_id: Option<String>, name: String) -> MessageType {
+    MessageType::ToolResponse(ToolResponse {
+        content: Value::String(content),
+        call_id,
+        name,
+        status: ToolStatus::Success,
+        duration_ms: 0,
+    })
+}
+
+// one chat completion style message, {role, content, tool_calls?, tool_call_id?}
+fn convert_chat_message(message: &Value) -> Vec<MessageType> {
+    let role = message.get("role").and_then(Value::as_str).unwrap_or("");
+    let content = message.get("content").map(content_text).unwrap_or_default();
+    match role {
+        "user" => vec![MessageType::User(UserMessage::new(content))],
+        "system" | "developer" if !content.trim().is_empty() => {
+            vec![MessageType::System(SystemMessage { content })]
+        }
+        "assistant" => {
+            let mut messages = Vec::new();
+            if !content.trim().is_empty() {
+                messages.push(MessageType::Assistant(AssistantMessage { content, model: None }));
+            }
+            if let Some(Value::Array(calls)) = message.get("tool_calls") {
+                let tool_calls: Vec<ToolCallFn> = calls
+                    .iter()
+                    .map(|call| {
+                        let function = call.get("function").unwrap_or(call);
+                        tool_call_message(
+                            function.get("name").and_then(Value::as_str).unwrap_or("tool"),
+                            call.get("id").and_then(Value::as_str).map(str::to_string),
+                            function.get("arguments").unwrap_or(&Value::Null),
+                        )
+                    })
+                    .collect();
+                if !tool_calls.is_empty() {
+                    let content = serde_json::to_string(&tool_calls).unwrap();
+                    messages.push(MessageType::ToolCall(ToolCall { content }));
+                }
+            }
+            messages
+        }
+        "tool" | "function" => {
+            let call_id = message.get("tool_call_id").and_then(Value::as_str).map(str::to_string);
+            let name = message.get("name").and_then(Value::as_str).unwrap_or("tool").to_string();
+            vec![tool_response(content, call_id, name)]
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn from_chat_messages(messages: &[Value]) -> Conversation {
+    let mut conversation = Conversation::default();
+    for message in messages {
+        for converted in convert_chat_message(message) {
+            conversation.push(converted);
+        }
+    }
+    conversation
+}
+
+// a message from the chatgpt data export, None for hidden or empty nodes
+fn convert_openai_message(message: &Value) -> Option<MessageType> {
+    let role = message.pointer("/author/role")?.as_str()?;
+    let content = content_text(message.get("content")?);
+    let hidden = message
+        .pointer("/metadata/is_visually_hidden_from_conversation")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    if hidden || content.trim().is_empty() {
+        return None;
+    }
+    let recipient = message.get("recipient").and_then(Value::as_str).unwrap_or("all");
+    match role {
+        "user" => Some(MessageType::User(UserMessage::new(content))),
+        "system" => Some(MessageType::System(SystemMessage { content })),
+        // assistant messages sent to a tool instead of the user are tool calls
+        "assistant" if recipient != "all" => {
+            let tool_call = tool_call_message(recipient, None, &Value::String(content));
+            Some(MessageType::ToolCall(ToolCall { content: serde_json::to_string(&vec![tool_call]).unwrap() }))
+        }
+        "assistant" => Some(MessageType::Assistant(AssistantMessage { content, model: None })),
+        "tool" => {
+            let name = message.pointer("/author/name").and_then(Value::as_str).unwrap_or("tool");
+            Some(tool_response(content, None, name.to_string()))
+        }
+        _ => None,
+    }
+}
+
+fn timestamp(value: Option<&Value>) -> Option<i64> {
+    value.and_then(Value::as_f64).map(|t| t as i64)
+}
+
+fn from_openai(value: &Value) -> Option<Conversation> {
+    let mapping = value.get("mapping")?.as_object()?;
+    let mut conversation = Conversation::default();
+    conversation.title = value.get("title").and_then(Value::as_str).map(str::to_string);
+    if let Some(created_at) = timestamp(value.get("create_time")) {
+        conversation.created_at = created_at;
+    }
+    if let Some(updated_at) = timestamp(value.get("update_time")) {
+        conversation.updated_at = updated_at;
+    }
+    // walk from the roots so parents always exist before their children,
+    // skipped nodes hand their place in the tree to their children
+    let mut placed: HashMap<&str, Option<usize>> = HashMap::new();
+    let mut queue: Vec<(&str, Option<usize>)> = mapping
+        .iter()
+        .filter(|(_, node)| node.get("parent").is_none_or(Value::is_null))
+        .map(|(id, _)| (id.as_str(), None))
+        .collect();
+    while let Some((id, parent)) = queue.pop() {
+        let Some(node) = mapping.get(id) else { continue };
+        let converted = node.get("message").and_then(convert_openai_message);
+        let idx = match converted {
+            Some(message) => {
+                let idx = conversation.add_child(parent, message);
+                if let Some(created_at) = timestamp(node.pointer("/message/create_time")) {
+                    conversation.nodes[idx].created_at = created_at;
+                }
+                Some(idx)
+            }
+            None => parent,
+        };
+        placed.insert(id, idx);
+        if let Some(Value::Array(children)) = node.get("children") {
+            for child in children.iter().rev().filter_map(Value::as_str) {
+                queue.push((child, idx));
+            }
+        }
+    }
+    if conversation.is_empty() {
+        return None;
+    }
+    let current = value
+        .get("current_node")
+        .and_then(Value::as_str)
+        .and_then(|id| placed.get(id).copied().flatten());
+    if let Some(current) = current {
+        conversation.select(current);
+    }
+    Some(conversation)
+}
+
+fn from_export(value: Value) -> Option<Conversation> {
+    let export: ConversationExport = serde_json::from_value(value).ok()?;
+    let mut conversation = export.conversation;
+    // a fresh id so importing the same file twice never overwrites a chat
+    conversation.id = uuid::Uuid::new_v4().to_string();
+    Some(conversation)
+}
+
+// accepts our json export, the chatgpt conversations.json and plain [{role, content}] lists
+pub fn parse_import(data: &str) -> Result<Vec<Conversation>, String> {
+    let value: Value = serde_json::from_str(data).map_err(|e| format!("not a json file: {}", e))?;
+    let conversations = match &value {
+        Value::Object(o) if o.get("format").and_then(Value::as_str) == Some(EXPORT_FORMAT) => {
+            from_export(value).into_iter().collect()
+        }
+        Value::Object(o) if o.contains_key("mapping") => from_openai(&value).into_iter().collect(),
+        Value::Object(o) => match o.get("messages") {
+            Some(Value::Array(messages)) => vec![from_chat_messages(messages)],
+            _ => Vec::new(),
+        },
+        Value::Array(items) if items.iter().any(|i| i.get("mapping").is_some()) => {
+            items.iter().filter_map(from_openai).collect()
+        }
+        Value::Array(items) if items.iter().any(|i| i.get("role").is_some()) => vec![from_chat_messages(items)],
+        _ => Vec::new(),
+    };
+    let conversations: Vec<Conversation> = conversations.into_iter().filter(|c| !c.is_empty()).collect();
+    if conversations.is_empty() {
+        return Err("no conversations found in file".to_string());
+    }
+    Ok(conversations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(message: &MessageType) -> String {
+        match message {
+            MessageType::User(m) => format!("user: {}", m.content),
+            MessageType::Assistant(m) => format!("assistant: {}", m.content),
+            MessageType::System(m) => format!("system: {}", m.content),
+            MessageType::ToolCall(m) => format!("tool_call: {}", m.content),
+            MessageType::ToolResponse(m) => format!("tool: {} {}", m.name, m.content),
+        }
+    }
+
+    fn texts(conversation: &Conversation) -> Vec<String> {
+        conversation.messages().iter().map(text).collect()
+    }
+
+    // trimmed down from a real conversations.json, the second answer is a regeneration
+    const CHATGPT: &str = r#"[{
+        "title": "Greeting",
+        "create_time": 1700000000.5,
+        "update_time": 1700000100.0,
+        "current_node": "a2",
+        "mapping": {
+            "root": {"id": "root", "message": null, "parent": null, "children": ["sys"]},
+            "sys": {
+                "id": "sys",
+                "message": {
+                    "author": {"role": "system"},
+                    "content": {"content_type": "text", "parts": [""]},
+                    "metadata": {"is_visually_hidden_from_conversation": true}
+                },
+                "parent": "root",
+                "children": ["u1"]
+            },
+            "u1": {
+                "id": "u1",
+                "message": {
+                    "author": {"role": "user"},
+                    "create_time": 1700000010.0,
+                    "content": {"content_type": "text", "parts": ["hi"]}
+                },
+                "parent": "sys",
+                "children": ["a1", "a2"]
+            },
+            "a1": {
+                "id": "a1",
+                "message": {"author": {"role": "assistant"}, "content": {"content_type": "text", "parts": ["hello"]}},
+                "parent": "u1",
+                "children": []
+            },
+            "a2": {
+                "id": "a2",
+                "message": {
+                    "author": {"role": "assistant"},
+                    "recipient": "browser",
+                    "content": {"content_type": "code", "text": "search(\"weather\")"}
+                },
+                "parent": "u1",
+                "children": ["t1"]
+            },
+            "t1": {
+                "id": "t1",
+                "message": {"author": {"role": "tool", "name": "browser"}, "content": {"content_type": "text", "parts": ["sunny"]}},
+                "parent": "a2",
+                "children": []
+            }
+        }
+    }]"#;
+
+    #[test]
+    fn chatgpt_export_keeps_branches_and_current_node() {
+        let conversations = parse_import(CHATGPT).unwrap();
+        assert_eq!(conversations.len(), 1);
+        let conversation = &conversations[0];
+        assert_eq!(conversation.title.as_deref(), Some("Greeting"));
+        assert_eq!(conversation.created_at, 1700000000);
+        assert_eq!(conversation.updated_at, 1700000100);
+        // the hidden system node is skipped and its child becomes the root
+        assert_eq!(conversation.nodes.len(), 4);
+        let root = conversation.active_path()[0];
+        assert_eq!(conversation.nodes[root].parent, None);
+        assert_eq!(conversation.nodes[root].created_at, 1700000010);
+        assert_eq!(
+            texts(conversation),
+            vec![
+                "user: hi".to_string(),
+                r#"tool_call: [{"name":"browser","arguments":{"input":"search(\"weather\")"},"call_id":null}]"#.to_string(),
+                "tool: browser \"sunny\"".to_string(),
+            ]
+        );
+        let answers = conversation.siblings(conversation.active_path()[1]);
+        assert_eq!(answers.len(), 2);
+        assert!(answers.iter().any(|idx| text(&conversation.nodes[*idx].message) == "assistant: hello"));
+    }
+
+    #[test]
+    fn plain_message_list_becomes_one_branch() {
+        let data = r#"[
+            {"role": "system", "content": "be brief"},
+            {"role": "user", "content": [{"type": "text", "text": "what is 2+2"}, {"type": "image_url", "image_url": {}}]},
+            {"role": "assistant", "content": null, "tool_calls": [
+                {"id": "c1", "type": "function", "function": {"name": "calculate", "arguments": "{\"expression\": \"2+2\"}"}}
+            ]},
+            {"role": "tool", "tool_call_id": "c1", "name": "calculate", "content": "4"},
+            {"role": "assistant", "content": "4"}
+        ]"#;
+        let conversations = parse_import(data).unwrap();
+        assert_eq!(conversations.len(), 1);
+        assert_eq!(
+            texts(&conversations[0]),
+            vec![
+                "system: be brief".to_string(),
+                "user: what is 2+2".to_string(),
+                r#"tool_call: [{"name":"calculate","arguments":{"expression":"2+2"},"call_id":"c1"}]"#.to_string(),
+                "tool: calculate \"4\"".to_string(),
+                "assistant: 4".to_string(),
+            ]
+        );
+        let with_key = format!(r#"{{"messages": {}}}"#, data);
+        assert_eq!(texts(&parse_import(&with_key).unwrap()[0]).len(), 5);
+    }
+
+    #[test]
+    fn unknown_files_are_rejected() {
+        assert!(parse_import("not json").is_err());
+        assert_eq!(parse_import(r#"{"foo": 1}"#).unwrap_err(), "no conversations found in file");
+        assert!(parse_import("[]").is_err());
+    }
+}
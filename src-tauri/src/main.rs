@@ -2,11 +2,35 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 #![allow(unused_variables)]
 mod api_req;
+mod api_server;
+mod attachment;
+mod builtin_tools;
+mod cli;
 mod commands;
+mod config;
+mod context;
+mod deep_link;
+mod conversation;
+mod documents;
+mod embedding;
+mod encoding;
+mod export;
+mod generation;
+mod hotkey;
+mod import;
+mod search;
 mod serde_obj;
+mod speech;
+mod summary;
 mod tokenizer;
+mod persona;
+mod profile;
+mod paths;
+mod quick_action;
+mod secrets;
 mod plugin_sys;
 mod utility;
+mod voice;
 
 use std::sync::Arc;
 
@@ -18,43 +42,24 @@ use tauri::{
 };
 use tauri_plugin_autostart::ManagerExt;
 use tauri_plugin_positioner::WindowExt as _;
-use tokenizer::MessageType;
+use conversation::Conversation;
 
-fn get_dir() -> std::path::PathBuf {
-    #[cfg(dev)]
-    {
-        let workking_test_dir = std::env::current_dir()
-            .unwrap()
-            .parent()
-            .unwrap()
-            .join("test_workdir");
-        if !workking_test_dir.exists() {
-            std::fs::create_dir(&workking_test_dir).unwrap();
-        }
-        return workking_test_dir;
-    }
-    #[cfg(not(dev))]
-    {
-        return std::env::current_exe()
-            .unwrap()
-            .parent()
-            .unwrap()
-            .to_path_buf();
+fn get_config() -> config::LoadedConfig {
+    // older versions kept everything next to the executable
+    let move_errors = paths::migrate_from_exe_dir();
+    let mut loaded = config::load_config(&config::config_path());
+    loaded.errors.extend(move_errors);
+    for error in &loaded.errors {
+        eprintln!("config error {}", error);
     }
+    loaded
 }
 
-fn get_config(app_handle: Option<AppHandle>) -> serde_obj::ConfigFile {
-    let file_path = get_dir().join("config.json");
-    let config: serde_obj::ConfigFile = if !file_path.exists() {
-        serde_json::from_slice(include_bytes!("cdn/config.json")).unwrap()
-    } else {
-        serde_json::from_str(&std::fs::read_to_string(file_path.clone()).unwrap()).unwrap()
-    };
-    config.clone().save_to_file(&file_path, app_handle);
-    config
-}
-
+// headless mode never opens a window, links and commands still run
 fn create_main_window(app: AppHandle) {
+    if cli::is_headless() {
+        return;
+    }
     let window_r = app.get_window("main");
     let window = if window_r.is_none() {
         WindowBuilder::new(&app, "main", tauri::WindowUrl::App("".into()))
@@ -85,6 +90,9 @@ fn create_main_window(app: AppHandle) {
 }
 
 fn create_setting_window(app: AppHandle) {
+    if cli::is_headless() {
+        return;
+    }
     let window_r = app.get_window("settings");
     let window = if window_r.is_none() {
         WindowBuilder::new(&app, "settings", tauri::WindowUrl::App("setting".into()))
@@ -109,6 +117,15 @@ fn tray_event(app: &AppHandle, event: tauri::SystemTrayEvent) {
             "settings" => {
                 create_setting_window(app.clone());
             }
+            action if action.starts_with(quick_action::MENU_PREFIX) => {
+                let app = app.clone();
+                let id = action[quick_action::MENU_PREFIX.len()..].to_string();
+                tauri::async_runtime::spawn(async move {
+                    if let Err(e) = quick_action::run_quick_action(app, id, None).await {
+                        eprintln!("quick action error {}", e);
+                    }
+                });
+            }
             "quit" => {
                 for window in app.windows().values() {
                     window.close().unwrap();
@@ -122,32 +139,134 @@ fn tray_event(app: &AppHandle, event: tauri::SystemTrayEvent) {
     }
 }
 
-#[allow(unused_mut)]
-fn create_sys_tray() -> SystemTray {
+fn create_tray_menu(config: &serde_obj::ConfigFile) -> SystemTrayMenu {
     let quit = CustomMenuItem::new("quit".to_string(), "Quit");
     let settings = CustomMenuItem::new("settings".to_string(), "Settings");
-    let tray_menu = SystemTrayMenu::new().add_item(settings).add_item(quit);
-    SystemTray::new().with_menu(tray_menu)
+    let mut tray_menu = SystemTrayMenu::new();
+    if let Some(quick_actions) = quick_action::tray_submenu(&config.quick_actions) {
+        tray_menu = tray_menu.add_submenu(quick_actions);
+    }
+    tray_menu.add_item(settings).add_item(quit)
+}
+
+fn create_sys_tray(config: &serde_obj::ConfigFile) -> SystemTray {
+    SystemTray::new().with_menu(create_tray_menu(config))
+}
+
+fn apply_startup(app: &AppHandle) {
+    let config = app.state::<Arc<Mutex<serde_obj::ConfigFile>>>();
+    if config.blocking_lock().run_on_startup {
+        let _ = app.autolaunch().enable();
+    } else {
+        let _ = app.autolaunch().disable();
+    }
+}
+
+fn register_hotkeys(app: &AppHandle) {
+    let config = app.state::<Arc<Mutex<serde_obj::ConfigFile>>>();
+    let keymap = config.blocking_lock().hotkeys.clone();
+    for error in hotkey::apply(app, &keymap) {
+        eprintln!("register hotkey {} for {} error {}", error.shortcut, error.action, error.error);
+    }
+}
+
+// editor plugins and scripts share the assistant through the local api
+fn start_api(app: &AppHandle) {
+    let config = app.state::<Arc<Mutex<serde_obj::ConfigFile>>>();
+    let api = config.blocking_lock().api.clone();
+    if cli::is_headless() || api.enabled {
+        api_server::spawn_serve(app, api.port);
+    }
+}
+
+fn reindex_documents(app: &AppHandle) {
+    let handle = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let config = handle.state::<Arc<Mutex<serde_obj::ConfigFile>>>();
+        let enabled = config.lock().await.documents.enabled;
+        if enabled {
+            if let Err(e) = documents::reindex(&handle).await {
+                eprintln!("index documents error {}", e);
+            }
+        }
+    });
+}
+
+// the first start can be a command too, then this process becomes the app
+fn handle_start_args(app: &AppHandle) {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let cwd = std::env::current_dir().unwrap_or_default();
+    cli::spawn_handle(app, &args, cwd);
+}
+
+fn register_deep_links(app: &AppHandle) {
+    let handle = app.clone();
+    let _ = tauri_plugin_deep_link::unregister(deep_link::SCHEME);
+    tauri_plugin_deep_link::prepare(deep_link::SCHEME);
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(link) = deep_link::find_in_args(&args) {
+        deep_link::spawn_handle(&handle, link.clone());
+    }
+    tauri_plugin_deep_link::register(deep_link::SCHEME, move |request| {
+        deep_link::spawn_handle(&handle, request);
+    })
+    .unwrap();
 }
 
 fn main() {
+    // a command that prints its answer waits here, the app itself runs in the other instance
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match cli::parse(&args) {
+        Ok(cli::Cli::Help) => {
+            cli::attach_console();
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Ok(cli::Cli::Request(request)) if request.prints_answer() && request.reply_to.is_none() => {
+            std::process::exit(cli::run_client(&args));
+        }
+        Ok(_) => {}
+        Err(e) => {
+            cli::attach_console();
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
     env_logger::Builder::new()
         .filter(None, log::LevelFilter::Info)
         .init();
-    let plugin_core = load_plugin();
-    let config = get_config(None);
-    let mess_list: Arc<Mutex<Vec<MessageType>>> = Arc::new(Mutex::new(Vec::new()));
-    tauri::Builder::default()
+    let config::LoadedConfig { config, errors: config_errors } = get_config();
+    let plugin_core = load_plugin(&config);
+    let token_counter = context::load_token_counter(&config.context);
+    let embedder = embedding::load_embedder(&config.embedding);
+    let speaker = speech::Speaker::new(&config.speech);
+    let secret_store = secrets::SecretStore::new(&config.secrets);
+    let conversation = if config.save_on_close {
+        Conversation::load_latest().unwrap_or_default()
+    } else {
+        Conversation::default()
+    };
+    let conversation: Arc<Mutex<Conversation>> = Arc::new(Mutex::new(conversation));
+    // tauri still needs a display on linux, headless only means nothing is shown
+    let headless = cli::is_headless();
+    let sys_tray = (!headless).then(|| create_sys_tray(&config));
+    let mut builder = tauri::Builder::default()
         .manage(plugin_core)
-        .manage(mess_list)
+        .manage(conversation)
+        .manage(token_counter)
+        .manage(embedder)
+        .manage(speaker)
+        .manage(voice::VoiceInput::default())
+        .manage(secret_store)
         .manage(Arc::new(Mutex::new(config)))
+        .manage(config::ConfigErrors(std::sync::Mutex::new(config_errors)))
         .on_window_event(|event| {
             let config: State<Arc<Mutex<serde_obj::ConfigFile>>> = event.window().state();
             match event.event() {
                 tauri::WindowEvent::Destroyed => {
                     if event.window().label() == "main" && !config.blocking_lock().save_on_close {
-                        let messages: State<Arc<Mutex<Vec<MessageType>>>> = event.window().state();
-                        messages.blocking_lock().clear();
+                        let conversation: State<Arc<Mutex<Conversation>>> = event.window().state();
+                        *conversation.blocking_lock() = Conversation::default();
                     }
                 }
                 tauri::WindowEvent::Resized(size) => {
@@ -173,42 +292,85 @@ fn main() {
                 }
                 _ => {}
             }
-        })
-        .system_tray(create_sys_tray())
-        .on_system_tray_event(|app: &AppHandle, event: SystemTrayEvent| tray_event(app, event))
+        });
+    if let Some(sys_tray) = sys_tray {
+        builder = builder
+            .system_tray(sys_tray)
+            .on_system_tray_event(|app: &AppHandle, event: SystemTrayEvent| tray_event(app, event));
+    }
+    builder
         // plugins
         .plugin(tauri_plugin_single_instance::init(|app, args, cwd| {
+            // a link opened while the app runs starts a second instance that ends up here
+            if let Some(link) = deep_link::find_in_args(&args) {
+                deep_link::spawn_handle(app, link.clone());
+            }
+            cli::spawn_handle(app, args.get(1..).unwrap_or_default(), std::path::PathBuf::from(&cwd));
             app.emit_all("new-instance", NewInstancePayload { args, cwd })
                 .unwrap();
         }))
         .plugin(tauri_plugin_positioner::init())
         .plugin(tauri_plugin_autostart::init(tauri_plugin_autostart::MacosLauncher::LaunchAgent, Some(vec![])))
-        // setup
-        .setup(|app| {
-            let hendler = app.handle();
-            let config = hendler.state::<Arc<Mutex<serde_obj::ConfigFile>>>();
-            if config.blocking_lock().run_on_startup {
-                let _ = hendler.autolaunch().enable();
-            } else {
-                let _ = hendler.autolaunch().disable();
-            }
-            Ok(())
-        })
+        // setup, a second .setup replaces the first one so everything starts from here
         .setup(|app| {
             let handle = app.handle();
-            let _ = tauri_plugin_deep_link::unregister("aihelper");
-            tauri_plugin_deep_link::prepare("aihelper");
-            tauri_plugin_deep_link::register("aihelper", move |request| {
-                handle.emit_all("scheme-request-received", request).unwrap();
-            })
-            .unwrap();
+            apply_startup(&handle);
+            if !cli::is_headless() {
+                register_hotkeys(&handle);
+            }
+            start_api(&handle);
+            reindex_documents(&handle);
+            handle_start_args(&handle);
+            register_deep_links(&handle);
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             crate::commands::md_to_html,
             crate::commands::new_message,
+            crate::commands::edit_message,
+            crate::commands::regenerate_message,
+            crate::commands::switch_branch,
             crate::commands::generate_uuid,
             crate::commands::get_messages,
+            crate::commands::get_personas,
+            crate::commands::save_personas,
+            crate::commands::set_conversation_persona,
+            crate::commands::get_profiles,
+            crate::commands::save_profiles,
+            crate::commands::set_conversation_profile,
+            crate::commands::get_generation_params,
+            crate::commands::save_generation_params,
+            crate::commands::set_conversation_params,
+            crate::commands::export_conversation,
+            crate::commands::import_conversations,
+            crate::commands::search_messages,
+            crate::commands::open_conversation,
+            crate::commands::get_documents_config,
+            crate::commands::save_documents_config,
+            crate::commands::reindex_documents,
+            crate::commands::attach_files,
+            crate::commands::get_quick_actions,
+            crate::commands::save_quick_actions,
+            crate::commands::run_quick_action,
+            crate::commands::get_hotkeys,
+            crate::commands::save_hotkeys,
+            crate::commands::new_conversation,
+            crate::commands::get_speech_config,
+            crate::commands::save_speech_config,
+            crate::commands::speak_text,
+            crate::commands::stop_speaking,
+            crate::commands::get_voice_config,
+            crate::commands::save_voice_config,
+            crate::commands::start_voice_input,
+            crate::commands::stop_voice_input,
+            crate::commands::get_secrets_status,
+            crate::commands::unlock_secrets,
+            crate::commands::set_secret,
+            crate::commands::delete_secret,
+            crate::commands::get_config,
+            crate::commands::update_config,
+            crate::commands::get_config_errors,
+            crate::commands::get_data_paths,
         ])
         .build(tauri::generate_context!())
         .expect("error while building tauri application")
@@ -0,0 +1,279 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use serde::Serialize;
+
+// same folder name the tauri path resolver uses for app_config_dir
+const APP_DIR: &str = "com.ai-helper.app";
+// a file with this name next to the executable keeps everything in that folder
+const PORTABLE_MARKER: &str = "portable";
+
+struct Dirs {
+    config: PathBuf,
+    data: PathBuf,
+    cache: PathBuf,
+    portable: bool,
+}
+
+static DIRS: OnceLock<Dirs> = OnceLock::new();
+
+fn exe_dir() -> PathBuf {
+    std::env::current_exe()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .to_path_buf()
+}
+
+pub fn is_portable_requested() -> bool {
+    std::env::args().any(|arg| arg == "--portable")
+        || std::env::var_os("RASAST_PORTABLE").is_some_and(|v| !v.is_empty() && v != "0")
+        || exe_dir().join(PORTABLE_MARKER).exists()
+}
+
+fn single(dir: PathBuf, portable: bool) -> Dirs {
+    Dirs { config: dir.clone(), data: dir.clone(), cache: dir, portable }
+}
+
+// config: ~/.config, %APPDATA%, ~/Library/Application Support
+// data: ~/.local/share, %APPDATA%, ~/Library/Application Support
+// cache: ~/.cache, %LOCALAPPDATA%, ~/Library/Caches
+fn resolve() -> Dirs {
+    #[cfg(dev)]
+    {
+        let workking_test_dir = std::env::current_dir()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .join("test_workdir");
+        return single(workking_test_dir, true);
+    }
+    #[cfg(not(dev))]
+    {
+        if is_portable_requested() {
+            return single(exe_dir(), true);
+        }
+        let platform = (
+            tauri::api::path::config_dir(),
+            tauri::api::path::data_dir(),
+            tauri::api::path::cache_dir(),
+        );
+        match platform {
+            (Some(config), Some(data), Some(cache)) => Dirs {
+                config: config.join(APP_DIR),
+                data: data.join(APP_DIR),
+                cache: cache.join(APP_DIR),
+                portable: false,
+            },
+            _ => {
+                eprintln!("no platform directories, keep files next to the executable");
+                single(exe_dir(), true)
+            }
+        }
+    }
+}
+
+fn dirs() -> &'static Dirs {
+    DIRS.get_or_init(resolve)
+}
+
+fn ensure(dir: &Path) -> PathBuf {
+    if let Err(e) = std::fs::create_dir_all(dir) {
+        eprintln!("create dir {} error {}", dir.display(), e);
+    }
+    dir.to_path_buf()
+}
+
+// config.json
+pub fn config_dir() -> PathBuf {
+    ensure(&dirs().config)
+}
+
+// chats, attachments, exports and secrets, everything that cannot be made again
+pub fn data_dir() -> PathBuf {
+    ensure(&dirs().data)
+}
+
+// indexes that are rebuilt when missing
+pub fn cache_dir() -> PathBuf {
+    ensure(&dirs().cache)
+}
+
+// for tokens and secrets, the folder is made when it is missing
+#[cfg(unix)]
+pub fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    use std::{io::Write as _, os::unix::fs::PermissionsExt as _};
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = std::fs::OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+    // an older file can have wider permissions than a new one would get
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    file.write_all(data)
+}
+
+// the data dir is already private to the user on windows
+#[cfg(not(unix))]
+pub fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, data)
+}
+
+// written next to the real file first so a crash never leaves half a file behind
+pub fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let write = || -> std::io::Result<()> {
+        let mut file = std::fs::File::create(&tmp)?;
+        std::io::Write::write_all(&mut file, data)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)
+    };
+    write().inspect_err(|_| {
+        let _ = std::fs::remove_file(&tmp);
+    })
+}
+
+pub fn plugins_dir() -> PathBuf {
+    data_dir().join("plugins")
+}
+
+pub fn is_portable() -> bool {
+    dirs().portable
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DataPaths {
+    pub config: PathBuf,
+    pub data: PathBuf,
+    pub cache: PathBuf,
+    pub plugins: PathBuf,
+    pub portable: bool,
+}
+
+pub fn data_paths() -> DataPaths {
+    DataPaths {
+        config: config_dir(),
+        data: data_dir(),
+        cache: cache_dir(),
+        plugins: plugins_dir(),
+        portable: is_portable(),
+    }
+}
+
+// where each file of the old layout next to the executable goes now
+fn legacy_targets() -> Vec<(&'static str, PathBuf)> {
+    vec![
+        ("config.json", config_dir()),
+        ("config.json.bak", config_dir()),
+        ("secrets.enc", data_dir()),
+        ("secrets.json", data_dir()),
+        ("conversations", data_dir()),
+        ("attachments", data_dir()),
+        ("exports", data_dir()),
+        ("plugins", data_dir()),
+        ("search.db", data_dir()),
+        ("documents.db", cache_dir()),
+    ]
+}
+
+fn copy_all(from: &Path, to: &Path) -> std::io::Result<()> {
+    if from.is_dir() {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_all(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        std::fs::copy(from, to).map(|_| ())
+    }
+}
+
+fn remove_all(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+// a rename when both are on one disk, otherwise a copy, the old files stay when they cannot be removed
+fn move_item(from: &Path, to: &Path) -> std::io::Result<()> {
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    if let Err(e) = copy_all(from, to) {
+        // half a copy would stop the next try
+        let _ = remove_all(to);
+        return Err(e);
+    }
+    if let Err(e) = remove_all(from) {
+        eprintln!("remove old {} error {}", from.display(), e);
+    }
+    Ok(())
+}
+
+// moves files of the old layout, runs before anything is loaded so nothing is open yet
+pub fn migrate_legacy_files(old_dir: &Path, targets: &[(&str, PathBuf)]) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (name, target_dir) in targets {
+        let from = old_dir.join(name);
+        let to = target_dir.join(name);
+        if !from.exists() || to.exists() || from == to {
+            continue;
+        }
+        match move_item(&from, &to) {
+            Ok(()) => println!("moved {} to {}", from.display(), to.display()),
+            Err(e) => errors.push(format!("cannot move {} to {}: {}", from.display(), to.display(), e)),
+        }
+    }
+    errors
+}
+
+pub fn migrate_from_exe_dir() -> Vec<String> {
+    if is_portable() {
+        return Vec::new();
+    }
+    migrate_legacy_files(&exe_dir(), &legacy_targets())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_files_are_moved_once() {
+        let root = std::env::temp_dir().join(format!("rasast-paths-{}", uuid::Uuid::new_v4()));
+        let old = root.join("exe");
+        let config = root.join("config");
+        let data = root.join("data");
+        std::fs::create_dir_all(old.join("conversations")).unwrap();
+        std::fs::create_dir_all(&config).unwrap();
+        std::fs::create_dir_all(&data).unwrap();
+        std::fs::write(old.join("config.json"), "{}").unwrap();
+        std::fs::write(old.join("conversations").join("a.json"), "[]").unwrap();
+        std::fs::write(data.join("search.db"), "new").unwrap();
+        std::fs::write(old.join("search.db"), "old").unwrap();
+        let targets = vec![
+            ("config.json", config.clone()),
+            ("conversations", data.clone()),
+            ("search.db", data.clone()),
+            ("plugins", data.clone()),
+        ];
+
+        assert!(migrate_legacy_files(&old, &targets).is_empty());
+        assert_eq!(std::fs::read_to_string(config.join("config.json")).unwrap(), "{}");
+        assert!(data.join("conversations").join("a.json").exists());
+        assert!(!old.join("config.json").exists());
+        // never overwrite what is already in the new place
+        assert_eq!(std::fs::read_to_string(data.join("search.db")).unwrap(), "new");
+        assert!(old.join("search.db").exists());
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+
+use crate::serde_obj::ConfigFile;
+
+pub const DEFAULT_PERSONA: &str = "Rasast";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Persona {
+    pub name: String,
+    pub system_prompt: String,
+}
+
+pub fn default_personas() -> Vec<Persona> {
+    vec![Persona {
+        name: DEFAULT_PERSONA.to_string(),
+        system_prompt: include_str!("cdn/system_prompt.md").trim_end().to_string(),
+    }]
+}
+
+pub fn default_persona_name() -> String {
+    DEFAULT_PERSONA.to_string()
+}
+
+pub fn validate(personas: &[Persona], default_persona: &str) -> Result<(), String> {
+    for (idx, persona) in personas.iter().enumerate() {
+        if persona.name.trim().is_empty() {
+            return Err("persona name cannot be empty".to_string());
+        }
+        if personas[..idx].iter().any(|p| p.name == persona.name) {
+            return Err(format!("persona {} is defined twice", persona.name));
+        }
+    }
+    if !personas.iter().any(|p| p.name == default_persona) {
+        return Err(format!("default persona {} not found", default_persona));
+    }
+    Ok(())
+}
+
+fn get_username() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "user".to_string())
+}
+
+// supported variables: {{date}} {{time}} {{weekday}} {{os}} {{username}} {{persona}}
+pub fn render_template(template: &str, persona_name: &str) -> String {
+    let now = chrono::Local::now();
+    template
+        .replace("{{date}}", &now.format("%Y-%m-%d").to_string())
+        .replace("{{time}}", &now.format("%H:%M").to_string())
+        .replace("{{weekday}}", &now.format("%A").to_string())
+        .replace("{{os}}", std::env::consts::OS)
+        .replace("{{username}}", &get_username())
+        .replace("{{persona}}", persona_name)
+}
+
+pub fn find_persona<'a>(config: &'a ConfigFile, name: Option<&str>) -> Option<&'a Persona> {
+    let name = name.unwrap_or(config.default_persona.as_str());
+    config
+        .personas
+        .iter()
+        .find(|p| p.name == name)
+        .or_else(|| config.personas.iter().find(|p| p.name == config.default_persona))
+        .or_else(|| config.personas.first())
+}
+
+pub fn get_system_prompt(config: &ConfigFile, name: Option<&str>) -> Option<String> {
+    let persona = find_persona(config, name)?;
+    if persona.system_prompt.trim().is_empty() {
+        return None;
+    }
+    Some(render_template(&persona.system_prompt, &persona.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn persona(name: &str, system_prompt: &str) -> Persona {
+        Persona { name: name.to_string(), system_prompt: system_prompt.to_string() }
+    }
+
+    fn config(personas: Vec<Persona>, default_persona: &str) -> ConfigFile {
+        let mut config = crate::config::default_config();
+        config.personas = personas;
+        config.default_persona = default_persona.to_string();
+        config
+    }
+
+    #[test]
+    fn variables_are_filled_in() {
+        let rendered = render_template("{{persona}} on {{os}}, {{date}} {{time}}", "Helper");
+        let (head, when) = rendered.split_once(", ").unwrap();
+        assert_eq!(head, format!("Helper on {}", std::env::consts::OS));
+        let (date, time) = when.split_once(' ').unwrap();
+        assert!(chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok(), "{}", date);
+        assert!(chrono::NaiveTime::parse_from_str(time, "%H:%M").is_ok(), "{}", time);
+        assert!(!render_template("{{weekday}}", "").contains('{'));
+    }
+
+    #[test]
+    fn unknown_placeholders_are_left_alone() {
+        assert_eq!(render_template("Hi {{name}} {persona}", "Helper"), "Hi {{name}} {persona}");
+    }
+
+    #[test]
+    fn empty_and_repeated_names_are_rejected() {
+        assert!(validate(&[persona("a", ""), persona("b", "")], "b").is_ok());
+        assert_eq!(validate(&[persona(" ", "")], " "), Err("persona name cannot be empty".to_string()));
+        assert_eq!(
+            validate(&[persona("a", "one"), persona("a", "two")], "a"),
+            Err("persona a is defined twice".to_string())
+        );
+        assert_eq!(validate(&[persona("a", "")], "b"), Err("default persona b not found".to_string()));
+    }
+
+    #[test]
+    fn unknown_names_fall_back_to_the_default() {
+        let config = config(vec![persona("a", "I am {{persona}}"), persona("b", " ")], "a");
+        assert_eq!(find_persona(&config, Some("b")).unwrap().name, "b");
+        assert_eq!(find_persona(&config, Some("gone")).unwrap().name, "a");
+        assert_eq!(get_system_prompt(&config, None), Some("I am a".to_string()));
+        // a blank prompt sends no system message at all
+        assert_eq!(get_system_prompt(&config, Some("b")), None);
+    }
+}
@@ -4,7 +4,7 @@ use dlopen2::symbor::Library;
 use rasast_plugin::{PluginManager, SafeValue};
 use serde_json::Value;
 
-use crate::get_dir;
+use crate::{builtin_tools::load_builtin_tools, paths::plugins_dir, serde_obj::ConfigFile};
 
 fn get_plugin_file_ext() -> String {
     let os_name = std::env::consts::OS;
@@ -21,9 +21,12 @@ fn get_plugin_file_ext() -> String {
     file_extension.to_string()
 }
 
+pub type BuiltinFn = fn(HashMap<String, Value>) -> Result<SafeValue, String>;
+
 #[derive(Clone)]
 pub struct PluginCore {
     plugin_lib: HashMap<String, Arc<Library>>,
+    builtin_func: HashMap<String, BuiltinFn>,
     map_func: HashMap<String, String>,
     plugin_info: Vec<Value>
 }
@@ -32,6 +35,7 @@ impl PluginCore {
     pub fn new() -> Self {
         Self {
             plugin_lib: HashMap::new(),
+            builtin_func: HashMap::new(),
             map_func: HashMap::new(),
             plugin_info: Vec::new(),
         }
@@ -50,37 +54,63 @@ impl PluginCore {
         }
         let init_func = init_func_r.unwrap()();
         self.plugin_lib.insert(init_func.id.clone(), Arc::new(plugin));
-        let (info_vec, callback_list) = init_func.get_commands();
-        self.plugin_info.extend(info_vec);
-        for i in callback_list {
-            self.map_func.insert(i, init_func.id.clone());
-        }
+        self.add_commands(&init_func);
         Ok(())
     }
 
+    pub fn add_builtin(&mut self, manager: PluginManager, mut callbacks: HashMap<String, BuiltinFn>) {
+        for name in self.add_commands(&manager) {
+            callbacks.remove(&name);
+        }
+        self.builtin_func.extend(callbacks);
+    }
+
+    // the first tool with a name keeps it, so the model never sees two tools called the same
+    fn add_commands(&mut self, manager: &PluginManager) -> Vec<String> {
+        let mut refused = Vec::new();
+        let (info_vec, callback_list) = manager.get_commands();
+        for (info, name) in info_vec.into_iter().zip(callback_list) {
+            if let Some(owner) = self.map_func.get(&name) {
+                eprintln!("tool {} of {} skipped, {} already has a tool with that name", name, manager.id, owner);
+                refused.push(name);
+                continue;
+            }
+            self.plugin_info.push(info);
+            self.map_func.insert(name, manager.id.clone());
+        }
+        refused
+    }
+
     pub fn get_plugin_info(&self) -> Vec<Value> {
         self.plugin_info.clone()
     }
 
-    pub fn call_fn(&self, name: &str, args: HashMap<String, Value>) -> SafeValue {
+    pub fn call_fn(&self, name: &str, args: HashMap<String, Value>) -> Result<SafeValue, String> {
         println!("call fn {}", name);
-        let id = self.map_func.get(name).unwrap();
-        let plugin = self.plugin_lib.get(id).unwrap();
+        let id_r = self.map_func.get(name);
+        if id_r.is_none() {
+            return Err(format!("tool {} not found", name));
+        }
+        if let Some(func) = self.builtin_func.get(name) {
+            return func(args);
+        }
+        let plugin = self.plugin_lib.get(id_r.unwrap()).unwrap();
         let func_r = unsafe { plugin.symbol::<fn(HashMap<String, SafeValue>) -> SafeValue>(name) };
         if func_r.is_err() {
-            panic!("func not found");
+            return Err(format!("tool {} not found in plugin", name));
         }
         let func = func_r.unwrap();
         let arg_safe_value = args.iter().map(|(k, v)| (k.clone(), v.into())).collect();
         let r = func(arg_safe_value);
-        r
+        Ok(r)
     }
 }
 
 
-pub fn load_plugin() -> PluginCore {
-    let plugin_dir = get_dir().join("plugins");
+pub fn load_plugin(config: &ConfigFile) -> PluginCore {
+    let plugin_dir = plugins_dir();
     let mut plugin_core = PluginCore::new();
+    load_builtin_tools(&mut plugin_core, &config.builtin_tools);
     if !plugin_dir.exists() {
         eprintln!("create plugin dir");
         let create_dir_r = std::fs::create_dir(&plugin_dir);
@@ -108,3 +138,37 @@ pub fn load_plugin() -> PluginCore {
     println!("load plugin {} success", plugin_core.get_plugin_info().len());
     plugin_core
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rasast_plugin::Function;
+
+    fn echo(_: HashMap<String, Value>) -> Result<SafeValue, String> {
+        Ok(SafeValue::new("builtin"))
+    }
+
+    fn other(_: HashMap<String, Value>) -> Result<SafeValue, String> {
+        Ok(SafeValue::new("other"))
+    }
+
+    #[test]
+    fn a_tool_name_is_only_registered_once() {
+        let mut core = PluginCore::new();
+        let mut first = PluginManager::new("builtin");
+        first.add_command(Function::new("echo", "first", vec![]));
+        core.add_builtin(first, HashMap::from([("echo".to_string(), echo as BuiltinFn)]));
+        let mut second = PluginManager::new("other");
+        second.add_command(Function::new("echo", "second", vec![]));
+        second.add_command(Function::new("other", "other", vec![]));
+        core.add_builtin(
+            second,
+            HashMap::from([("echo".to_string(), other as BuiltinFn), ("other".to_string(), other as BuiltinFn)]),
+        );
+        let info = core.get_plugin_info();
+        assert_eq!(info.len(), 2);
+        assert_eq!(info[0]["function"]["description"], "first");
+        assert_eq!(info[1]["function"]["name"], "other");
+        assert_eq!(core.call_fn("echo", HashMap::new()).unwrap().to_serde::<String>(), "builtin");
+    }
+}
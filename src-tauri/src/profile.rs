@@ -0,0 +1,223 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{async_runtime::Mutex, Manager as _, State};
+
+use crate::{
+    api_req::BACKEND_NAME,
+    conversation::Conversation,
+    generation::GenerationParams,
+    plugin_sys::PluginCore,
+    serde_obj::ConfigFile,
+};
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+    // a gradio space that streams tokens for a raw prompt
+    #[default]
+    Gradio,
+    // any server with the openai api, e.g. llama.cpp, ollama or vllm
+    #[serde(rename = "openai")]
+    OpenAi,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptTemplate {
+    // rendered here, sent as one prompt
+    #[default]
+    MistralV3,
+    // chat messages, the server applies the model template (openai backend only)
+    Chat,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ModelProfile {
+    pub name: String,
+    #[serde(default)]
+    pub backend: BackendKind,
+    // the space url for gradio, the api base like http://localhost:11434/v1 for openai
+    pub url: String,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub template: PromptTemplate,
+    // on top of the generation settings, below the conversation overrides
+    #[serde(default)]
+    pub params: GenerationParams,
+    // images are sent as image parts instead of a note (chat template only)
+    #[serde(default)]
+    pub multimodal: bool,
+    // the name of the secret holding the api key, never the key itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteTask {
+    Chat,
+    // a chat turn that offers tools to the model
+    Tools,
+    QuickAction,
+    Summary,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RoutingRule {
+    pub task: RouteTask,
+    pub profile: String,
+}
+
+pub fn default_profiles() -> Vec<ModelProfile> {
+    vec![ModelProfile {
+        name: DEFAULT_PROFILE.to_string(),
+        backend: BackendKind::Gradio,
+        url: format!("https://{}", BACKEND_NAME),
+        model: "mistral-7b-instruct-v0.3".to_string(),
+        template: PromptTemplate::MistralV3,
+        params: GenerationParams::default(),
+        multimodal: false,
+        api_key: None,
+    }]
+}
+
+pub fn default_profile_name() -> String {
+    DEFAULT_PROFILE.to_string()
+}
+
+pub fn validate(profiles: &[ModelProfile], default_profile: &str, routing: &[RoutingRule]) -> Result<(), String> {
+    for (idx, profile) in profiles.iter().enumerate() {
+        if profile.name.trim().is_empty() {
+            return Err("profile name cannot be empty".to_string());
+        }
+        if profiles[..idx].iter().any(|p| p.name == profile.name) {
+            return Err(format!("profile {} is defined twice", profile.name));
+        }
+        if !profile.url.starts_with("http://") && !profile.url.starts_with("https://") {
+            return Err(format!("profile {} needs an http or https url", profile.name));
+        }
+        if profile.backend == BackendKind::Gradio && profile.template == PromptTemplate::Chat {
+            return Err(format!("profile {}: the gradio backend only takes a rendered prompt", profile.name));
+        }
+        if profile.backend == BackendKind::OpenAi && profile.template != PromptTemplate::Chat {
+            return Err(format!("profile {}: the openai backend takes chat messages", profile.name));
+        }
+        if profile.backend == BackendKind::OpenAi && profile.model.trim().is_empty() {
+            return Err(format!("profile {} needs a model name", profile.name));
+        }
+        if let Some(name) = &profile.api_key {
+            if !crate::secrets::valid_name(name) {
+                return Err(format!("profile {}: {} is not a secret name", profile.name, name));
+            }
+        }
+        profile.params.validate().map_err(|e| format!("profile {}: {}", profile.name, e))?;
+    }
+    if !profiles.iter().any(|p| p.name == default_profile) {
+        return Err(format!("default profile {} not found", default_profile));
+    }
+    for (idx, rule) in routing.iter().enumerate() {
+        if !profiles.iter().any(|p| p.name == rule.profile) {
+            return Err(format!("routing rule uses unknown profile {}", rule.profile));
+        }
+        if routing[..idx].iter().any(|r| r.task == rule.task) {
+            return Err("every task can only have one routing rule".to_string());
+        }
+    }
+    Ok(())
+}
+
+fn find<'a>(config: &'a ConfigFile, name: &str) -> Option<&'a ModelProfile> {
+    config.profiles.iter().find(|p| p.name == name)
+}
+
+fn rule(config: &ConfigFile, task: RouteTask) -> Option<&ModelProfile> {
+    let rule = config.routing.iter().find(|r| r.task == task)?;
+    find(config, &rule.profile)
+}
+
+// a profile picked for the conversation wins over the chat and tools rules,
+// background tasks follow their rule first
+pub fn resolve(config: &ConfigFile, conversation_profile: Option<&str>, task: RouteTask) -> ModelProfile {
+    let picked = conversation_profile.and_then(|name| find(config, name));
+    let routed = match task {
+        RouteTask::Chat => picked.or_else(|| rule(config, RouteTask::Chat)),
+        RouteTask::Tools => picked
+            .or_else(|| rule(config, RouteTask::Tools))
+            .or_else(|| rule(config, RouteTask::Chat)),
+        RouteTask::QuickAction | RouteTask::Summary => rule(config, task).or(picked),
+    };
+    routed
+        .or_else(|| find(config, &config.default_profile))
+        .or_else(|| config.profiles.first())
+        .cloned()
+        .unwrap_or_else(|| default_profiles().remove(0))
+}
+
+// a chat turn only counts as a tools turn when tools go with the request, and resolve
+// still sends it to the chat rule when there is no tools rule
+pub fn route_task(task: RouteTask, tools: &[Value]) -> RouteTask {
+    match task {
+        RouteTask::Chat if !tools.is_empty() => RouteTask::Tools,
+        task => task,
+    }
+}
+
+pub async fn select_profile(app: &tauri::AppHandle, task: RouteTask) -> ModelProfile {
+    let task = route_task(task, &app.state::<PluginCore>().get_plugin_info());
+    let conversation: State<Arc<Mutex<Conversation>>> = app.state();
+    let picked = conversation.lock().await.profile.clone();
+    let config: State<Arc<Mutex<ConfigFile>>> = app.state();
+    let config = config.lock().await;
+    resolve(&config, picked.as_deref(), task)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(name: &str) -> ModelProfile {
+        ModelProfile { name: name.to_string(), ..default_profiles().remove(0) }
+    }
+
+    fn routed(routing: &[(RouteTask, &str)]) -> ConfigFile {
+        let mut config = crate::config::default_config();
+        config.profiles = vec![profile(DEFAULT_PROFILE), profile("chat"), profile("tools"), profile("quick")];
+        config.routing = routing
+            .iter()
+            .map(|(task, profile)| RoutingRule { task: *task, profile: profile.to_string() })
+            .collect();
+        config
+    }
+
+    #[test]
+    fn chat_is_a_tools_turn_only_with_tools() {
+        let tools = vec![serde_json::json!({"type": "function", "function": {"name": "now"}})];
+        assert_eq!(route_task(RouteTask::Chat, &[]), RouteTask::Chat);
+        assert_eq!(route_task(RouteTask::Chat, &tools), RouteTask::Tools);
+        assert_eq!(route_task(RouteTask::Summary, &tools), RouteTask::Summary);
+    }
+
+    #[test]
+    fn tools_turns_use_the_chat_rule_without_a_tools_rule() {
+        let config = routed(&[(RouteTask::Chat, "chat")]);
+        assert_eq!(resolve(&config, None, RouteTask::Tools).name, "chat");
+        assert_eq!(resolve(&config, None, RouteTask::Chat).name, "chat");
+        let config = routed(&[(RouteTask::Chat, "chat"), (RouteTask::Tools, "tools")]);
+        assert_eq!(resolve(&config, None, RouteTask::Tools).name, "tools");
+        assert_eq!(resolve(&config, None, RouteTask::Chat).name, "chat");
+    }
+
+    #[test]
+    fn the_picked_profile_wins_over_chat_rules_only() {
+        let config = routed(&[(RouteTask::Tools, "tools"), (RouteTask::QuickAction, "quick")]);
+        assert_eq!(resolve(&config, Some("chat"), RouteTask::Tools).name, "chat");
+        assert_eq!(resolve(&config, Some("chat"), RouteTask::QuickAction).name, "quick");
+        assert_eq!(resolve(&config, Some("chat"), RouteTask::Summary).name, "chat");
+        assert_eq!(resolve(&config, Some("gone"), RouteTask::Summary).name, DEFAULT_PROFILE);
+    }
+}
@@ -0,0 +1,152 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tauri::{async_runtime::Mutex, CustomMenuItem, Manager as _, State, SystemTrayMenu, SystemTraySubmenu};
+
+use crate::{
+    api_req::get_completion,
+    commands::generate_reply,
+    conversation::Conversation,
+    profile::{select_profile, RouteTask},
+    serde_obj::{ConfigFile, QuickActionEventPayload, ToolStatus},
+    tokenizer::{MessageType, UserMessage},
+};
+
+pub const MENU_PREFIX: &str = "quick_action:";
+// a clipboard with a whole file in it is not a quick action anymore
+const MAX_INPUT_CHARS: usize = 16 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct QuickAction {
+    pub id: String,
+    pub label: String,
+    // {{text}} is replaced by the clipboard or selected text
+    pub prompt: String,
+    // true: answer silently and put the result on the clipboard, false: ask in the chat window
+    #[serde(default)]
+    pub copy_result: bool,
+}
+
+// the bundled config is the only place the defaults are written down, it always has the field
+// so the serde default that calls this is never used while reading it
+pub fn default_quick_actions() -> Vec<QuickAction> {
+    crate::config::default_config().quick_actions
+}
+
+pub fn render_prompt(action: &QuickAction, text: &str) -> String {
+    if action.prompt.contains("{{text}}") {
+        action.prompt.replace("{{text}}", text)
+    } else {
+        format!("{}\n\n{}", action.prompt, text)
+    }
+}
+
+pub fn validate(actions: &[QuickAction]) -> Result<(), String> {
+    for (idx, action) in actions.iter().enumerate() {
+        if action.id.trim().is_empty() || action.label.trim().is_empty() {
+            return Err("quick actions need an id and a label".to_string());
+        }
+        if actions[..idx].iter().any(|a| a.id == action.id) {
+            return Err(format!("quick action {} is defined twice", action.id));
+        }
+    }
+    Ok(())
+}
+
+// on linux the primary selection is the selected text, the clipboard is the fallback everywhere
+fn read_input() -> Result<String, String> {
+    let mut clipboard = arboard::Clipboard::new().map_err(|e| e.to_string())?;
+    #[cfg(target_os = "linux")]
+    {
+        use arboard::{GetExtLinux, LinuxClipboardKind};
+        if let Ok(text) = clipboard.get().clipboard(LinuxClipboardKind::Primary).text() {
+            if !text.trim().is_empty() {
+                return Ok(text);
+            }
+        }
+    }
+    clipboard.get_text().map_err(|e| e.to_string())
+}
+
+fn write_output(text: &str) -> Result<(), String> {
+    let mut clipboard = arboard::Clipboard::new().map_err(|e| e.to_string())?;
+    clipboard.set_text(text).map_err(|e| e.to_string())
+}
+
+pub fn tray_submenu(actions: &[QuickAction]) -> Option<SystemTraySubmenu> {
+    if actions.is_empty() {
+        return None;
+    }
+    let mut menu = SystemTrayMenu::new();
+    for action in actions {
+        menu = menu.add_item(CustomMenuItem::new(format!("{}{}", MENU_PREFIX, action.id), &action.label));
+    }
+    Some(SystemTraySubmenu::new("Quick actions", menu))
+}
+
+fn emit(app: &tauri::AppHandle, payload: QuickActionEventPayload) {
+    let _ = app.emit_all("quick-action", payload);
+}
+
+// input replaces the clipboard, e.g. a file from the command line; the answer comes back
+pub async fn run_quick_action(app: tauri::AppHandle, id: String, input: Option<String>) -> Result<String, String> {
+    let config_s: State<Arc<Mutex<ConfigFile>>> = app.state();
+    let action = config_s
+        .lock()
+        .await
+        .quick_actions
+        .iter()
+        .find(|a| a.id == id)
+        .cloned()
+        .ok_or(format!("quick action {} not found", id))?;
+    // arboard talks to the display server, keep it off the async workers
+    let text = match input {
+        Some(text) => text,
+        None => tauri::async_runtime::spawn_blocking(read_input)
+            .await
+            .map_err(|e| e.to_string())??,
+    };
+    if text.trim().is_empty() {
+        return Err("the clipboard has no text".to_string());
+    }
+    let text: String = text.chars().take(MAX_INPUT_CHARS).collect();
+    let prompt = render_prompt(&action, &text);
+    let uuid = uuid::Uuid::new_v4().to_string();
+    let mut payload = QuickActionEventPayload {
+        action: action.id.clone(),
+        uuid: uuid.clone(),
+        in_chat: !action.copy_result,
+        status: ToolStatus::Running,
+        result: None,
+    };
+    if !action.copy_result {
+        crate::create_main_window(app.clone());
+        let conversation: State<Arc<Mutex<Conversation>>> = app.state();
+        conversation.lock().await.push(MessageType::User(UserMessage::new(prompt)));
+        emit(&app, payload.clone());
+        let answer = generate_reply(app.clone(), uuid, RouteTask::QuickAction).await;
+        payload.status = ToolStatus::Success;
+        emit(&app, payload);
+        return answer.map(|text| text.trim().to_string());
+    }
+    emit(&app, payload.clone());
+    let profile = select_profile(&app, RouteTask::QuickAction).await;
+    let result = get_completion(&app, profile, vec![MessageType::User(UserMessage::new(prompt))], None).await;
+    let result = match result {
+        Ok(result) => result.trim().to_string(),
+        Err(e) => {
+            payload.status = ToolStatus::Error;
+            payload.result = Some(e.clone());
+            emit(&app, payload);
+            return Err(e);
+        }
+    };
+    let copy = result.clone();
+    tauri::async_runtime::spawn_blocking(move || write_output(&copy))
+        .await
+        .map_err(|e| e.to_string())??;
+    payload.status = ToolStatus::Success;
+    payload.result = Some(result.clone());
+    emit(&app, payload);
+    Ok(result)
+}
@@ -0,0 +1,409 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    conversation::Conversation,
+    embedding::{self, Embedder},
+    export::conversation_title,
+    tokenizer::MessageType,
+};
+
+// snippet() marks matches with these, they cannot appear in chat text sent through the ui
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+const EMBED_BATCH: usize = 16;
+const EMBED_MAX_CHARS: usize = 2000;
+const PREVIEW_CHARS: usize = 200;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS conversations (
+    id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    revision TEXT NOT NULL
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS messages USING fts5(
+    content,
+    conversation_id UNINDEXED,
+    node UNINDEXED,
+    role UNINDEXED,
+    created_at UNINDEXED,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+CREATE TABLE IF NOT EXISTS embeddings (
+    conversation_id TEXT NOT NULL,
+    node INTEGER NOT NULL,
+    model TEXT NOT NULL,
+    vector BLOB NOT NULL,
+    PRIMARY KEY (conversation_id, node)
+);
+";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SnippetPart {
+    pub text: String,
+    pub highlight: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SearchHit {
+    pub conversation_id: String,
+    pub title: String,
+    pub node: usize,
+    pub role: String,
+    pub created_at: i64,
+    pub snippet: Vec<SnippetPart>,
+    // bigger is better for both kinds of search
+    pub score: f32,
+}
+
+fn err(e: rusqlite::Error) -> String {
+    e.to_string()
+}
+
+// the index only mirrors the json files, it can be deleted at any time and is rebuilt on the next search
+pub fn open_index(path: &Path) -> Result<Connection, String> {
+    let conn = Connection::open(path).map_err(err)?;
+    // two searches typed quickly can overlap
+    conn.busy_timeout(Duration::from_secs(5)).map_err(err)?;
+    conn.execute_batch(SCHEMA).map_err(err)?;
+    Ok(conn)
+}
+
+// nodes are never changed once written, so the count is enough to notice new messages
+// in a conversation that has not been saved again yet
+fn revision(conversation: &Conversation) -> String {
+    format!("{}:{}", conversation.updated_at, conversation.nodes.len())
+}
+
+// saved chats are only parsed again when their file changed
+fn file_revision(path: &Path) -> Option<String> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("file:{}:{}", modified.as_nanos(), metadata.len()))
+}
+
+fn index_conversation(conn: &Connection, conversation: &Conversation, revision: &str) -> Result<(), rusqlite::Error> {
+    conn.execute("DELETE FROM messages WHERE conversation_id = ?1", params![conversation.id])?;
+    conn.execute("DELETE FROM embeddings WHERE conversation_id = ?1", params![conversation.id])?;
+    let mut insert = conn.prepare(
+        "INSERT INTO messages (content, conversation_id, node, role, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    // every branch is searchable, not only the active one
+    for (idx, node) in conversation.nodes.iter().enumerate() {
+        let (role, content) = match &node.message {
+            MessageType::User(m) => ("user", &m.content),
+            MessageType::Assistant(m) => ("assistant", &m.content),
+            _ => continue,
+        };
+        if content.trim().is_empty() {
+            continue;
+        }
+        insert.execute(params![content, conversation.id, idx as i64, role, node.created_at])?;
+    }
+    conn.execute(
+        "INSERT OR REPLACE INTO conversations (id, title, revision) VALUES (?1, ?2, ?3)",
+        params![conversation.id, conversation_title(conversation), revision],
+    )?;
+    Ok(())
+}
+
+// bring the index in line with the saved chats in `dir` and the open one,
+// only files that changed since the last search are read again
+pub fn sync(conn: &mut Connection, dir: &Path, current: &Conversation) -> Result<(), String> {
+    let indexed: HashMap<String, String> = {
+        let mut stmt = conn.prepare("SELECT id, revision FROM conversations").map_err(err)?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(err)?;
+        rows.collect::<Result<_, _>>().map_err(err)?
+    };
+    let mut present: HashSet<String> = HashSet::new();
+    let tx = conn.transaction().map_err(err)?;
+    if let Ok(files) = std::fs::read_dir(dir) {
+        for path in files.filter_map(|file| Some(file.ok()?.path())) {
+            if path.extension().is_none_or(|e| e != "json") {
+                continue;
+            }
+            let Some(id) = path.file_stem().map(|s| s.to_string_lossy().to_string()) else {
+                continue;
+            };
+            // the open chat can be ahead of its file
+            if id == current.id {
+                continue;
+            }
+            let Some(file_revision) = file_revision(&path) else {
+                continue;
+            };
+            if indexed.get(&id) != Some(&file_revision) {
+                let Some(conversation) = Conversation::read_file(&path) else {
+                    continue;
+                };
+                index_conversation(&tx, &conversation, &file_revision).map_err(err)?;
+            }
+            present.insert(id);
+        }
+    }
+    if !current.is_empty() {
+        let revision = revision(current);
+        if indexed.get(&current.id) != Some(&revision) {
+            index_conversation(&tx, current, &revision).map_err(err)?;
+        }
+        present.insert(current.id.clone());
+    }
+    for id in indexed.keys() {
+        if !present.contains(id) {
+            tx.execute("DELETE FROM messages WHERE conversation_id = ?1", params![id]).map_err(err)?;
+            tx.execute("DELETE FROM embeddings WHERE conversation_id = ?1", params![id]).map_err(err)?;
+            tx.execute("DELETE FROM conversations WHERE id = ?1", params![id]).map_err(err)?;
+        }
+    }
+    tx.commit().map_err(err)
+}
+
+// embeds the messages that have no vector for the current model yet
+pub fn sync_embeddings(conn: &mut Connection, embedder: &dyn Embedder) -> Result<(), String> {
+    let model = embedder.model().to_string();
+    conn.execute("DELETE FROM embeddings WHERE model != ?1", params![model]).map_err(err)?;
+    let missing: Vec<(String, i64, String)> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT m.conversation_id, m.node, m.content FROM messages m
+                 LEFT JOIN embeddings e ON e.conversation_id = m.conversation_id AND e.node = m.node
+                 WHERE e.node IS NULL",
+            )
+            .map_err(err)?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .map_err(err)?;
+        rows.collect::<Result<_, _>>().map_err(err)?
+    };
+    for batch in missing.chunks(EMBED_BATCH) {
+        let texts: Vec<String> = batch
+            .iter()
+            .map(|(_, _, content)| content.chars().take(EMBED_MAX_CHARS).collect())
+            .collect();
+        let vectors = embedder.embed(&texts)?;
+        let tx = conn.transaction().map_err(err)?;
+        for ((conversation_id, node, _), vector) in batch.iter().zip(vectors) {
+            tx.execute(
+                "INSERT OR REPLACE INTO embeddings (conversation_id, node, model, vector) VALUES (?1, ?2, ?3, ?4)",
+                params![conversation_id, node, model, embedding::to_bytes(&vector)],
+            )
+            .map_err(err)?;
+        }
+        tx.commit().map_err(err)?;
+    }
+    Ok(())
+}
+
+// every word has to match, the last one also as a prefix so results show up while typing
+pub fn build_match_query(query: &str) -> Option<String> {
+    let words: Vec<String> = query
+        .split_whitespace()
+        .map(|w| format!("\"{}\"", w.replace('"', "\"\"")))
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+    Some(format!("{}*", words.join(" ")))
+}
+
+pub fn split_snippet(snippet: &str) -> Vec<SnippetPart> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut highlight = false;
+    for c in snippet.chars() {
+        if c == MATCH_START || c == MATCH_END {
+            if !text.is_empty() {
+                parts.push(SnippetPart { text: std::mem::take(&mut text), highlight });
+            }
+            highlight = c == MATCH_START;
+            continue;
+        }
+        text.push(c);
+    }
+    if !text.is_empty() {
+        parts.push(SnippetPart { text, highlight });
+    }
+    parts
+}
+
+fn preview(content: &str) -> Vec<SnippetPart> {
+    let text = content.split_whitespace().collect::<Vec<&str>>().join(" ");
+    let mut short: String = text.chars().take(PREVIEW_CHARS).collect();
+    if text.chars().count() > PREVIEW_CHARS {
+        short.push('…');
+    }
+    vec![SnippetPart { text: short, highlight: false }]
+}
+
+pub fn search_text(conn: &Connection, query: &str, limit: usize) -> Result<Vec<SearchHit>, String> {
+    let Some(query) = build_match_query(query) else {
+        return Ok(Vec::new());
+    };
+    let mut stmt = conn
+        .prepare(
+            "SELECT messages.conversation_id, conversations.title, messages.node, messages.role,
+                    messages.created_at, snippet(messages, 0, char(2), char(3), '…', 24), bm25(messages)
+             FROM messages JOIN conversations ON conversations.id = messages.conversation_id
+             WHERE messages MATCH ?1 ORDER BY bm25(messages) LIMIT ?2",
+        )
+        .map_err(err)?;
+    let rows = stmt
+        .query_map(params![query, limit as i64], |row| {
+            let snippet: String = row.get(5)?;
+            let rank: f64 = row.get(6)?;
+            Ok(SearchHit {
+                conversation_id: row.get(0)?,
+                title: row.get(1)?,
+                node: row.get::<_, i64>(2)? as usize,
+                role: row.get(3)?,
+                created_at: row.get(4)?,
+                snippet: split_snippet(&snippet),
+                // bm25 is negative, lower is better
+                score: -rank as f32,
+            })
+        })
+        .map_err(err)?;
+    rows.collect::<Result<_, _>>().map_err(err)
+}
+
+pub fn search_semantic(
+    conn: &Connection,
+    embedder: &dyn Embedder,
+    query: &str,
+    limit: usize,
+) -> Result<Vec<SearchHit>, String> {
+    if query.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let target = embedder
+        .embed(&[query.to_string()])?
+        .pop()
+        .ok_or("embedding model returned nothing")?;
+    let mut scored: Vec<(String, i64, f32)> = {
+        let mut stmt = conn
+            .prepare("SELECT conversation_id, node, vector FROM embeddings WHERE model = ?1")
+            .map_err(err)?;
+        let rows = stmt
+            .query_map(params![embedder.model()], |row| {
+                let vector: Vec<u8> = row.get(2)?;
+                Ok((row.get(0)?, row.get(1)?, embedding::similarity(&target, &embedding::from_bytes(&vector))))
+            })
+            .map_err(err)?;
+        rows.collect::<Result<_, _>>().map_err(err)?
+    };
+    scored.sort_by(|a, b| b.2.total_cmp(&a.2));
+    scored.truncate(limit);
+    let mut stmt = conn
+        .prepare(
+            "SELECT conversations.title, messages.role, messages.created_at, messages.content
+             FROM messages JOIN conversations ON conversations.id = messages.conversation_id
+             WHERE messages.conversation_id = ?1 AND messages.node = ?2",
+        )
+        .map_err(err)?;
+    let mut hits = Vec::new();
+    for (conversation_id, node, score) in scored {
+        let hit = stmt.query_row(params![conversation_id, node], |row| {
+            let content: String = row.get(3)?;
+            Ok(SearchHit {
+                conversation_id: conversation_id.clone(),
+                title: row.get(0)?,
+                node: node as usize,
+                role: row.get(1)?,
+                created_at: row.get(2)?,
+                snippet: preview(&content),
+                score,
+            })
+        });
+        match hit {
+            Ok(hit) => hits.push(hit),
+            Err(rusqlite::Error::QueryReturnedNoRows) => {}
+            Err(e) => return Err(err(e)),
+        }
+    }
+    Ok(hits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::{AssistantMessage, UserMessage};
+
+    #[test]
+    fn match_query_quotes_every_word() {
+        assert_eq!(build_match_query("  "), None);
+        assert_eq!(build_match_query("rust lang").unwrap(), r#""rust" "lang"*"#);
+        // fts5 operators and syntax stay plain text inside the quotes
+        assert_eq!(build_match_query("NOT a* (b) c:d").unwrap(), r#""NOT" "a*" "(b)" "c:d"*"#);
+        assert_eq!(build_match_query(r#"say "hi""#).unwrap(), r#""say" """hi"""*"#);
+    }
+
+    #[test]
+    fn match_query_is_valid_fts5() {
+        let conn = open_index(Path::new(":memory:")).unwrap();
+        conn.execute(
+            "INSERT INTO messages (content, conversation_id, node, role, created_at) VALUES (?1, 'c', 0, 'user', 0)",
+            params![r#"he said "hi" (twice) AND left"#],
+        )
+        .unwrap();
+        for query in [r#""hi""#, "(twice)", "AND", "NEAR(", "^he", "said -", "hi\"\""] {
+            let fts = build_match_query(query).unwrap();
+            let count: i64 = conn
+                .query_row("SELECT count(*) FROM messages WHERE messages MATCH ?1", params![fts], |row| row.get(0))
+                .unwrap_or_else(|e| panic!("{} failed: {}", query, e));
+            assert!(count <= 1);
+        }
+    }
+
+    #[test]
+    fn snippet_marks_become_parts() {
+        let snippet = format!("…the {}quick{} fox {}jumps{}", MATCH_START, MATCH_END, MATCH_START, MATCH_END);
+        assert_eq!(
+            split_snippet(&snippet),
+            vec![
+                SnippetPart { text: "…the ".to_string(), highlight: false },
+                SnippetPart { text: "quick".to_string(), highlight: true },
+                SnippetPart { text: " fox ".to_string(), highlight: false },
+                SnippetPart { text: "jumps".to_string(), highlight: true },
+            ]
+        );
+        assert_eq!(split_snippet(""), vec![]);
+        assert_eq!(split_snippet("plain"), vec![SnippetPart { text: "plain".to_string(), highlight: false }]);
+    }
+
+    #[test]
+    fn sync_reads_only_changed_files_and_drops_deleted_ones() {
+        let dir = std::env::temp_dir().join(format!("rasast-search-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut saved = Conversation::default();
+        saved.push(MessageType::User(UserMessage::new("where is the lighthouse".to_string())));
+        let path = dir.join(format!("{}.json", saved.id));
+        std::fs::write(&path, serde_json::to_string(&saved).unwrap()).unwrap();
+        let mut current = Conversation::default();
+        current.push(MessageType::Assistant(AssistantMessage { content: "the lighthouse is north".to_string(), model: None }));
+
+        let mut conn = open_index(Path::new(":memory:")).unwrap();
+        sync(&mut conn, &dir, &current).unwrap();
+        assert_eq!(search_text(&conn, "lighthouse", 10).unwrap().len(), 2);
+
+        // the file revision is stored so the next sync skips the unchanged file
+        let revision: String = conn
+            .query_row("SELECT revision FROM conversations WHERE id = ?1", params![saved.id], |row| row.get(0))
+            .unwrap();
+        assert_eq!(Some(revision), file_revision(&path));
+
+        std::fs::remove_file(&path).unwrap();
+        sync(&mut conn, &dir, &current).unwrap();
+        let hits = search_text(&conn, "lighthouse", 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].conversation_id, current.id);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
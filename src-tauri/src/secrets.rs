@@ -0,0 +1,381 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::OnceLock,
+};
+
+use base64::Engine as _;
+use chacha20poly1305::{
+    aead::{Aead as _, AeadCore as _, KeyInit as _, OsRng},
+    XChaCha20Poly1305,
+};
+use rand::RngCore as _;
+use serde::{Deserialize, Serialize};
+use tauri::{Manager as _, State};
+
+use crate::paths::{data_dir, write_private};
+
+const SERVICE: &str = "rasast";
+const FILE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretsBackendKind {
+    // the os keyring, the encrypted file when there is none (e.g. linux without a secret service)
+    #[default]
+    Auto,
+    Keyring,
+    File,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SecretsConfig {
+    #[serde(default)]
+    pub backend: SecretsBackendKind,
+}
+
+pub trait SecretBackend: Send + Sync {
+    fn kind(&self) -> SecretsBackendKind;
+    fn get(&self, name: &str) -> Result<Option<String>, String>;
+    fn set(&self, name: &str, value: &str) -> Result<(), String>;
+    fn delete(&self, name: &str) -> Result<(), String>;
+    // only the file needs a passphrase
+    fn is_locked(&self) -> bool {
+        false
+    }
+    fn unlock(&self, _passphrase: &str) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+pub struct KeyringBackend;
+
+impl KeyringBackend {
+    fn entry(name: &str) -> Result<keyring::Entry, String> {
+        keyring::Entry::new(SERVICE, name).map_err(|e| e.to_string())
+    }
+
+    // a missing entry means the keyring works, anything else means it does not
+    pub fn available() -> bool {
+        matches!(
+            Self::entry("__probe__").map(|entry| entry.get_password()),
+            Ok(Ok(_)) | Ok(Err(keyring::Error::NoEntry))
+        )
+    }
+}
+
+impl SecretBackend for KeyringBackend {
+    fn kind(&self) -> SecretsBackendKind {
+        SecretsBackendKind::Keyring
+    }
+
+    fn get(&self, name: &str) -> Result<Option<String>, String> {
+        match Self::entry(name)?.get_password() {
+            Ok(value) => Ok(Some(value)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn set(&self, name: &str, value: &str) -> Result<(), String> {
+        Self::entry(name)?.set_password(value).map_err(|e| e.to_string())
+    }
+
+    fn delete(&self, name: &str) -> Result<(), String> {
+        match Self::entry(name)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SecretFile {
+    version: u32,
+    salt: String,
+    nonce: String,
+    data: String,
+}
+
+struct Unlocked {
+    key: [u8; 32],
+    salt: [u8; 16],
+    secrets: BTreeMap<String, String>,
+}
+
+// every secret in one file, encrypted with a key derived from the passphrase
+pub struct FileBackend {
+    path: PathBuf,
+    state: std::sync::Mutex<Option<Unlocked>>,
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32], String> {
+    let mut key = [0u8; 32];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| e.to_string())?;
+    Ok(key)
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, String> {
+    base64::engine::general_purpose::STANDARD
+        .decode(value)
+        .map_err(|_| "the secrets file is damaged".to_string())
+}
+
+impl FileBackend {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, state: std::sync::Mutex::new(None) }
+    }
+
+    fn read(&self, passphrase: &str) -> Result<Unlocked, String> {
+        let text = std::fs::read_to_string(&self.path).map_err(|e| e.to_string())?;
+        let file: SecretFile = serde_json::from_str(&text).map_err(|_| "the secrets file is damaged".to_string())?;
+        if file.version != FILE_VERSION {
+            return Err(format!("unknown secrets file version {}", file.version));
+        }
+        let salt: [u8; 16] = decode(&file.salt)?.try_into().map_err(|_| "the secrets file is damaged")?;
+        let key = derive_key(passphrase, &salt)?;
+        let nonce = decode(&file.nonce)?;
+        if nonce.len() != 24 {
+            return Err("the secrets file is damaged".to_string());
+        }
+        let cipher = XChaCha20Poly1305::new(&key.into());
+        let plain = cipher
+            .decrypt(nonce.as_slice().into(), decode(&file.data)?.as_slice())
+            .map_err(|_| "wrong passphrase".to_string())?;
+        let secrets = serde_json::from_slice(&plain).map_err(|e| e.to_string())?;
+        Ok(Unlocked { key, salt, secrets })
+    }
+
+    // a fresh nonce on every write, written next to the file first so a crash keeps the old one
+    fn write(&self, unlocked: &Unlocked) -> Result<(), String> {
+        let cipher = XChaCha20Poly1305::new(&unlocked.key.into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let plain = serde_json::to_vec(&unlocked.secrets).map_err(|e| e.to_string())?;
+        let data = cipher.encrypt(&nonce, plain.as_slice()).map_err(|e| e.to_string())?;
+        let engine = base64::engine::general_purpose::STANDARD;
+        let file = SecretFile {
+            version: FILE_VERSION,
+            salt: engine.encode(unlocked.salt),
+            nonce: engine.encode(nonce),
+            data: engine.encode(data),
+        };
+        let tmp = self.path.with_extension("tmp");
+        write_private(&tmp, serde_json::to_string(&file).unwrap().as_bytes()).map_err(|e| e.to_string())?;
+        std::fs::rename(&tmp, &self.path).map_err(|e| e.to_string())
+    }
+
+    fn with_unlocked<T>(&self, f: impl FnOnce(&mut Unlocked) -> Result<T, String>) -> Result<T, String> {
+        let mut state = self.state.lock().unwrap();
+        let unlocked = state.as_mut().ok_or("the secrets file is locked, unlock it in the settings")?;
+        f(unlocked)
+    }
+}
+
+impl SecretBackend for FileBackend {
+    fn kind(&self) -> SecretsBackendKind {
+        SecretsBackendKind::File
+    }
+
+    fn get(&self, name: &str) -> Result<Option<String>, String> {
+        self.with_unlocked(|unlocked| Ok(unlocked.secrets.get(name).cloned()))
+    }
+
+    fn set(&self, name: &str, value: &str) -> Result<(), String> {
+        self.with_unlocked(|unlocked| {
+            unlocked.secrets.insert(name.to_string(), value.to_string());
+            self.write(unlocked)
+        })
+    }
+
+    fn delete(&self, name: &str) -> Result<(), String> {
+        self.with_unlocked(|unlocked| {
+            unlocked.secrets.remove(name);
+            self.write(unlocked)
+        })
+    }
+
+    fn is_locked(&self) -> bool {
+        self.state.lock().unwrap().is_none()
+    }
+
+    // the first passphrase creates the file
+    fn unlock(&self, passphrase: &str) -> Result<(), String> {
+        if passphrase.is_empty() {
+            return Err("the passphrase cannot be empty".to_string());
+        }
+        let unlocked = if self.path.exists() {
+            self.read(passphrase)?
+        } else {
+            let mut salt = [0u8; 16];
+            OsRng.fill_bytes(&mut salt);
+            let unlocked = Unlocked { key: derive_key(passphrase, &salt)?, salt, secrets: BTreeMap::new() };
+            self.write(&unlocked)?;
+            unlocked
+        };
+        *self.state.lock().unwrap() = Some(unlocked);
+        Ok(())
+    }
+}
+
+pub fn get_secrets_path() -> PathBuf {
+    data_dir().join("secrets.enc")
+}
+
+// names only, the keyring cannot list what we stored
+fn get_index_path() -> PathBuf {
+    data_dir().join("secrets.json")
+}
+
+pub struct SecretStore {
+    kind: SecretsBackendKind,
+    // picked on first use, probing the keyring at startup can show a prompt before the window
+    backend: OnceLock<Box<dyn SecretBackend>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SecretsStatus {
+    pub backend: SecretsBackendKind,
+    pub locked: bool,
+    pub names: Vec<String>,
+}
+
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+impl SecretStore {
+    pub fn new(config: &SecretsConfig) -> Self {
+        Self { kind: config.backend, backend: OnceLock::new() }
+    }
+
+    fn backend(&self) -> &dyn SecretBackend {
+        self.backend
+            .get_or_init(|| {
+                let use_keyring = match self.kind {
+                    SecretsBackendKind::Keyring => true,
+                    SecretsBackendKind::File => false,
+                    SecretsBackendKind::Auto => KeyringBackend::available(),
+                };
+                if use_keyring {
+                    Box::new(KeyringBackend)
+                } else {
+                    Box::new(FileBackend::new(get_secrets_path()))
+                }
+            })
+            .as_ref()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        std::fs::read_to_string(get_index_path())
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    fn save_names(&self, names: &[String]) -> Result<(), String> {
+        write_private(&get_index_path(), serde_json::to_string_pretty(names).unwrap().as_bytes())
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn status(&self) -> SecretsStatus {
+        SecretsStatus {
+            backend: self.backend().kind(),
+            locked: self.backend().is_locked(),
+            names: self.names(),
+        }
+    }
+
+    pub fn unlock(&self, passphrase: &str) -> Result<(), String> {
+        self.backend().unlock(passphrase)
+    }
+
+    pub fn get(&self, name: &str) -> Result<String, String> {
+        self.backend().get(name)?.ok_or(format!("secret {} is not set", name))
+    }
+
+    pub fn set(&self, name: &str, value: &str) -> Result<(), String> {
+        if !valid_name(name) {
+            return Err("secret names may only use letters, digits, '_', '-' and '.'".to_string());
+        }
+        self.backend().set(name, value)?;
+        let mut names = self.names();
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+            names.sort();
+            self.save_names(&names)?;
+        }
+        Ok(())
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), String> {
+        self.backend().delete(name)?;
+        let mut names = self.names();
+        names.retain(|n| n != name);
+        self.save_names(&names)
+    }
+}
+
+// the value behind a secret name from the config, None when the config names none
+pub fn resolve_secret(app: &tauri::AppHandle, name: Option<&str>) -> Result<Option<String>, String> {
+    let Some(name) = name.filter(|n| !n.is_empty()) else {
+        return Ok(None);
+    };
+    let store: State<SecretStore> = app.state();
+    store.get(name).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rasast-{}-{}.enc", name, uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let path = temp_path("secrets");
+        let backend = FileBackend::new(path.clone());
+        assert!(backend.is_locked());
+        assert!(backend.get("key").is_err());
+        backend.unlock("correct horse").unwrap();
+        backend.set("openai", "sk-123").unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("sk-123"));
+
+        let reopened = FileBackend::new(path.clone());
+        assert_eq!(reopened.unlock("wrong"), Err("wrong passphrase".to_string()));
+        reopened.unlock("correct horse").unwrap();
+        assert_eq!(reopened.get("openai").unwrap(), Some("sk-123".to_string()));
+        reopened.delete("openai").unwrap();
+        assert_eq!(reopened.get("openai").unwrap(), None);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn tampered_file_is_rejected() {
+        let path = temp_path("tampered");
+        let backend = FileBackend::new(path.clone());
+        backend.unlock("pass").unwrap();
+        backend.set("a", "b").unwrap();
+        let mut file: SecretFile = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let mut data = decode(&file.data).unwrap();
+        data[0] ^= 1;
+        file.data = base64::engine::general_purpose::STANDARD.encode(data);
+        std::fs::write(&path, serde_json::to_string(&file).unwrap()).unwrap();
+        assert!(FileBackend::new(path.clone()).unlock("pass").is_err());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_is_private_and_its_folder_is_made() {
+        use std::os::unix::fs::PermissionsExt as _;
+        let dir = std::env::temp_dir().join(format!("rasast-secrets-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("data").join("secrets.enc");
+        FileBackend::new(path.clone()).unlock("pass").unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -4,15 +4,74 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tauri_plugin_autostart::ManagerExt;
 
+use crate::{
+    api_server::ApiConfig,
+    builtin_tools::BuiltinToolsConfig,
+    config::{write_config, CONFIG_VERSION},
+    context::ContextConfig,
+    documents::DocumentsConfig,
+    embedding::EmbeddingConfig,
+    generation::GenerationParams,
+    hotkey::{default_hotkeys, Keymap},
+    persona::{default_persona_name, default_personas, Persona},
+    profile::{default_profile_name, default_profiles, ModelProfile, RoutingRule},
+    quick_action::{default_quick_actions, QuickAction},
+    secrets::SecretsConfig,
+    speech::SpeechConfig,
+    voice::VoiceConfig,
+    summary::SummaryConfig,
+};
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ConfigFile {
+    #[serde(default = "config_version")]
+    pub version: u32,
     #[serde(default)]
     pub run_on_startup: bool,
-    pub save_on_close: bool
+    #[serde(default)]
+    pub save_on_close: bool,
+    #[serde(default)]
+    pub builtin_tools: BuiltinToolsConfig,
+    #[serde(default = "default_personas")]
+    pub personas: Vec<Persona>,
+    #[serde(default = "default_persona_name")]
+    pub default_persona: String,
+    #[serde(default)]
+    pub context: ContextConfig,
+    #[serde(default)]
+    pub summary: SummaryConfig,
+    #[serde(default)]
+    pub generation: GenerationParams,
+    #[serde(default = "default_profiles")]
+    pub profiles: Vec<ModelProfile>,
+    #[serde(default = "default_profile_name")]
+    pub default_profile: String,
+    #[serde(default)]
+    pub routing: Vec<RoutingRule>,
+    #[serde(default)]
+    pub embedding: EmbeddingConfig,
+    #[serde(default)]
+    pub documents: DocumentsConfig,
+    #[serde(default = "default_quick_actions")]
+    pub quick_actions: Vec<QuickAction>,
+    #[serde(default = "default_hotkeys")]
+    pub hotkeys: Keymap,
+    #[serde(default)]
+    pub speech: SpeechConfig,
+    #[serde(default)]
+    pub voice: VoiceConfig,
+    #[serde(default)]
+    pub secrets: SecretsConfig,
+    #[serde(default)]
+    pub api: ApiConfig,
+}
+
+fn config_version() -> u32 {
+    CONFIG_VERSION
 }
 
 impl ConfigFile {
-    pub fn save_to_file(self, path: &PathBuf, app: Option<tauri::AppHandle>) {
+    pub fn save_to_file(self, path: &PathBuf, app: Option<tauri::AppHandle>) -> Result<(), String> {
         if let Some(app) = app {
             if self.run_on_startup {
                 let _ = app.autolaunch().enable();
@@ -20,7 +79,7 @@ impl ConfigFile {
                 let _ = app.autolaunch().disable();
             }
         }
-        std::fs::write(path, serde_json::to_string(&self).unwrap()).unwrap();
+        write_config(path, &self)
     }
 }
 
@@ -36,7 +95,7 @@ pub struct MessageEventPayload {
     pub uuid: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ToolCallFn {
     pub name: String,
     pub arguments: HashMap<String, Value>,
@@ -48,3 +107,50 @@ pub struct TokenResponse {
     pub text: String,
     pub special: bool,
 }
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolStatus {
+    #[default]
+    Running,
+    Success,
+    Error,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ToolEventPayload {
+    pub uuid: String,
+    pub call_id: String,
+    pub name: String,
+    pub arguments: HashMap<String, Value>,
+    pub status: ToolStatus,
+    pub result: Option<Value>,
+    pub duration_ms: Option<u64>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VoiceStatus {
+    Recording,
+    Transcribing,
+    Done,
+    Error,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VoiceEventPayload {
+    pub status: VoiceStatus,
+    // the transcript when done, the error otherwise
+    pub text: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QuickActionEventPayload {
+    pub action: String,
+    // uuid of the streamed reply when the action runs in the chat
+    pub uuid: String,
+    pub in_chat: bool,
+    pub status: ToolStatus,
+    // the copied text, or the error
+    pub result: Option<String>,
+}
@@ -0,0 +1,634 @@
+use std::{
+    io::Write as _,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{channel, Sender},
+        Arc,
+    },
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::{Manager as _, State};
+
+const SENTENCE_ENDS: [char; 6] = ['.', '!', '?', '。', '！', '？'];
+// a dot after these, or after a single letter like an initial, does not end the sentence
+const ABBREVIATIONS: [&str; 9] = ["mr", "mrs", "ms", "dr", "prof", "st", "vs", "e.g", "i.e"];
+const POLL_INTERVAL: Duration = Duration::from_millis(30);
+
+pub trait SpeechBackend: Send + Sync {
+    // blocks until the text was spoken or stop was called
+    fn speak(&self, text: &str) -> Result<(), String>;
+    // called from another thread while speak is running
+    fn stop(&self);
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SpeechEngine {
+    // sapi on windows, say on macos, speech-dispatcher or espeak on linux
+    #[default]
+    System,
+    Espeak,
+    SpeechDispatcher,
+    Piper,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PiperConfig {
+    #[serde(default)]
+    pub binary: Option<String>,
+    // path to the .onnx voice, the .onnx.json has to be next to it
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SpeechConfig {
+    // read every reply aloud while it streams
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub engine: SpeechEngine,
+    // voice name for espeak, speech-dispatcher, say and the windows voices
+    #[serde(default)]
+    pub voice: Option<String>,
+    #[serde(default)]
+    pub piper: PiperConfig,
+}
+
+fn find_program(name: &str) -> Option<PathBuf> {
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+// waits for the child without holding the lock so stop can kill it
+fn wait_child(slot: &std::sync::Mutex<Option<Child>>) -> Result<(), String> {
+    loop {
+        let mut guard = slot.lock().unwrap();
+        let Some(child) = guard.as_mut() else {
+            return Ok(());
+        };
+        match child.try_wait() {
+            Ok(Some(_)) => {
+                *guard = None;
+                return Ok(());
+            }
+            Ok(None) => {}
+            Err(e) => {
+                *guard = None;
+                return Err(e.to_string());
+            }
+        }
+        drop(guard);
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// a console program started from the app would open a window for every sentence
+fn hide_window(command: &mut Command) -> &mut Command {
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt as _;
+        const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+    command
+}
+
+fn kill_child(slot: &std::sync::Mutex<Option<Child>>) {
+    if let Some(mut child) = slot.lock().unwrap().take() {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+// a speech program that reads the text from stdin, so text starting with "-" is never an option
+pub struct CommandBackend {
+    program: PathBuf,
+    args: Vec<String>,
+    // run on stop for programs that hand the text to a daemon
+    stop_args: Option<Vec<String>>,
+    child: std::sync::Mutex<Option<Child>>,
+}
+
+impl CommandBackend {
+    fn new(program: PathBuf, args: Vec<String>, stop_args: Option<Vec<String>>) -> Self {
+        Self { program, args, stop_args, child: std::sync::Mutex::new(None) }
+    }
+}
+
+impl SpeechBackend for CommandBackend {
+    fn speak(&self, text: &str) -> Result<(), String> {
+        let mut child = hide_window(&mut Command::new(&self.program))
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("{}: {}", self.program.display(), e))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(text.as_bytes());
+        }
+        *self.child.lock().unwrap() = Some(child);
+        wait_child(&self.child)
+    }
+
+    fn stop(&self) {
+        kill_child(&self.child);
+        if let Some(stop_args) = &self.stop_args {
+            let _ = Command::new(&self.program).args(stop_args).status();
+        }
+    }
+}
+
+// piper writes a wav file that is then played by the platform player
+pub struct PiperBackend {
+    binary: PathBuf,
+    model: PathBuf,
+    output: PathBuf,
+    child: std::sync::Mutex<Option<Child>>,
+    stopped: AtomicBool,
+}
+
+fn player_command(file: &Path) -> Command {
+    #[cfg(target_os = "windows")]
+    {
+        let mut command = Command::new("powershell");
+        command.args([
+            "-NoProfile",
+            "-Command",
+            &format!("(New-Object Media.SoundPlayer '{}').PlaySync()", file.display()),
+        ]);
+        command
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let mut command = Command::new("afplay");
+        command.arg(file);
+        command
+    }
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    {
+        let mut command = Command::new("aplay");
+        command.arg("-q").arg(file);
+        command
+    }
+}
+
+impl SpeechBackend for PiperBackend {
+    fn speak(&self, text: &str) -> Result<(), String> {
+        self.stopped.store(false, Ordering::SeqCst);
+        let mut child = hide_window(&mut Command::new(&self.binary))
+            .arg("--model")
+            .arg(&self.model)
+            .arg("--output_file")
+            .arg(&self.output)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("{}: {}", self.binary.display(), e))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(text.as_bytes());
+        }
+        *self.child.lock().unwrap() = Some(child);
+        wait_child(&self.child)?;
+        if self.stopped.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let player = hide_window(&mut player_command(&self.output))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+        *self.child.lock().unwrap() = Some(player);
+        wait_child(&self.child)
+    }
+
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        kill_child(&self.child);
+    }
+}
+
+// system.speech reads with the sapi voices, one powershell keeps running between sentences
+// because starting it and loading the assembly takes about a second, stop kills it
+#[cfg(target_os = "windows")]
+pub struct SapiBackend {
+    args: Vec<String>,
+    child: std::sync::Mutex<Option<Child>>,
+    // only the speaking thread uses the pipes, so stop never waits for a sentence
+    pipes: std::sync::Mutex<Option<(std::process::ChildStdin, std::io::BufReader<std::process::ChildStdout>)>>,
+}
+
+#[cfg(target_os = "windows")]
+impl SpeechBackend for SapiBackend {
+    fn speak(&self, text: &str) -> Result<(), String> {
+        use std::io::BufRead as _;
+        let mut pipes = self.pipes.lock().unwrap();
+        // the process was killed by stop, the next sentence starts a new one
+        if self.child.lock().unwrap().is_none() {
+            *pipes = None;
+        }
+        if pipes.is_none() {
+            let mut child = hide_window(&mut Command::new("powershell"))
+                .args(&self.args)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(|e| format!("powershell: {}", e))?;
+            let stdin = child.stdin.take().ok_or("powershell has no stdin")?;
+            let stdout = child.stdout.take().ok_or("powershell has no stdout")?;
+            *self.child.lock().unwrap() = Some(child);
+            *pipes = Some((stdin, std::io::BufReader::new(stdout)));
+        }
+        let (stdin, stdout) = pipes.as_mut().unwrap();
+        // one line is one sentence, the script answers once it was spoken
+        let line = text.replace(['\r', '\n'], " ");
+        let mut answer = String::new();
+        let spoken = writeln!(stdin, "{}", line)
+            .and_then(|_| stdin.flush())
+            .and_then(|_| stdout.read_line(&mut answer));
+        if matches!(spoken, Ok(n) if n > 0) {
+            return Ok(());
+        }
+        *pipes = None;
+        if self.child.lock().unwrap().is_none() {
+            return Ok(());
+        }
+        kill_child(&self.child);
+        Err("the windows voice stopped, check the voice name in the speech settings".to_string())
+    }
+
+    fn stop(&self) {
+        kill_child(&self.child);
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl Drop for SapiBackend {
+    fn drop(&mut self) {
+        kill_child(&self.child);
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn sapi_backend(voice: Option<&str>) -> Result<Arc<dyn SpeechBackend>, String> {
+    let mut script = String::from(
+        "Add-Type -AssemblyName System.Speech; $s = New-Object System.Speech.Synthesis.SpeechSynthesizer; ",
+    );
+    if let Some(voice) = voice {
+        script.push_str(&format!("$s.SelectVoice('{}'); ", voice.replace('\'', "''")));
+    }
+    // stdin is read as utf-8, the console code page would mangle anything else
+    script.push_str(
+        "$in = New-Object IO.StreamReader([Console]::OpenStandardInput(), [Text.Encoding]::UTF8); \
+         while ($null -ne ($line = $in.ReadLine())) { $s.Speak($line); [Console]::Out.WriteLine('done'); [Console]::Out.Flush() }",
+    );
+    let args = vec!["-NoProfile".to_string(), "-NonInteractive".to_string(), "-Command".to_string(), script];
+    Ok(Arc::new(SapiBackend {
+        args,
+        child: std::sync::Mutex::new(None),
+        pipes: std::sync::Mutex::new(None),
+    }))
+}
+
+fn system_backend(voice: Option<&str>) -> Result<Arc<dyn SpeechBackend>, String> {
+    #[cfg(target_os = "windows")]
+    {
+        sapi_backend(voice)
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let mut args = vec!["-f".to_string(), "-".to_string()];
+        if let Some(voice) = voice {
+            args.extend(["-v".to_string(), voice.to_string()]);
+        }
+        Ok(Arc::new(CommandBackend::new(PathBuf::from("say"), args, None)))
+    }
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    {
+        if find_program("spd-say").is_some() {
+            speech_dispatcher_backend(voice)
+        } else {
+            espeak_backend(voice)
+        }
+    }
+}
+
+fn espeak_backend(voice: Option<&str>) -> Result<Arc<dyn SpeechBackend>, String> {
+    let program = find_program("espeak-ng")
+        .or_else(|| find_program("espeak"))
+        .or_else(|| find_program("espeak-ng.exe"))
+        .ok_or("espeak-ng was not found in PATH")?;
+    let mut args = vec!["--stdin".to_string()];
+    if let Some(voice) = voice {
+        args.extend(["-v".to_string(), voice.to_string()]);
+    }
+    Ok(Arc::new(CommandBackend::new(program, args, None)))
+}
+
+fn speech_dispatcher_backend(voice: Option<&str>) -> Result<Arc<dyn SpeechBackend>, String> {
+    let program = find_program("spd-say").ok_or("spd-say was not found in PATH")?;
+    // -e reads stdin, -w waits until the sentence was spoken
+    let mut args = vec!["-w".to_string(), "-e".to_string()];
+    if let Some(voice) = voice {
+        args.extend(["-y".to_string(), voice.to_string()]);
+    }
+    Ok(Arc::new(CommandBackend::new(program, args, Some(vec!["-C".to_string()]))))
+}
+
+fn piper_backend(config: &PiperConfig) -> Result<Arc<dyn SpeechBackend>, String> {
+    let binary = match &config.binary {
+        Some(binary) => PathBuf::from(binary),
+        None => find_program("piper").ok_or("piper was not found in PATH")?,
+    };
+    let model = config.model.as_ref().ok_or("speech.piper.model is not set")?;
+    let output = std::env::temp_dir().join(format!("rasast-piper-{}.wav", std::process::id()));
+    Ok(Arc::new(PiperBackend {
+        binary,
+        model: PathBuf::from(model),
+        output,
+        child: std::sync::Mutex::new(None),
+        stopped: AtomicBool::new(false),
+    }))
+}
+
+pub fn load_backend(config: &SpeechConfig) -> Result<Arc<dyn SpeechBackend>, String> {
+    let voice = config.voice.as_deref().filter(|v| !v.trim().is_empty());
+    match config.engine {
+        SpeechEngine::System => system_backend(voice),
+        SpeechEngine::Espeak => espeak_backend(voice),
+        SpeechEngine::SpeechDispatcher => speech_dispatcher_backend(voice),
+        SpeechEngine::Piper => piper_backend(&config.piper),
+    }
+}
+
+fn is_abbreviation(before_dot: &str) -> bool {
+    let word = before_dot
+        .rsplit(char::is_whitespace)
+        .next()
+        .unwrap_or("")
+        .trim_start_matches(|c: char| !c.is_alphanumeric())
+        .to_lowercase();
+    let mut chars = word.chars();
+    let single_letter = matches!((chars.next(), chars.next()), (Some(c), None) if c.is_alphabetic());
+    single_letter || ABBREVIATIONS.contains(&word.as_str())
+}
+
+// byte offset after every complete sentence in `text[from..]`, fenced code never ends a sentence
+// and is skipped later by clean_for_speech
+fn sentence_ends(text: &str, from: usize, is_final: bool) -> Vec<usize> {
+    let mut ends = Vec::new();
+    let mut in_fence = false;
+    let mut chars = text.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        if text[idx..].starts_with("```") {
+            in_fence = !in_fence;
+            chars.next();
+            chars.next();
+            continue;
+        }
+        let end = idx + c.len_utf8();
+        if in_fence || end <= from {
+            continue;
+        }
+        // "3.14" is not the end of a sentence, full width marks need no space after them
+        let next = chars.peek().map(|(_, c)| *c);
+        let closes = c == '\n'
+            || (SENTENCE_ENDS.contains(&c)
+                && (!c.is_ascii() || next.map_or(is_final, char::is_whitespace))
+                && !(c == '.' && is_abbreviation(&text[..idx])));
+        if closes {
+            ends.push(end);
+        }
+    }
+    if is_final && ends.last().copied().unwrap_or(from) < text.len() {
+        ends.push(text.len());
+    }
+    ends
+}
+
+// what a voice should not read: code blocks, inline code markers and markdown symbols
+pub fn clean_for_speech(text: &str) -> String {
+    let prose: String = text.split("```").step_by(2).collect::<Vec<&str>>().join(" ");
+    let cleaned: String = prose
+        .chars()
+        .filter(|c| !matches!(c, '*' | '_' | '#' | '>' | '|' | '~' | '`'))
+        .collect();
+    cleaned.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// tracks how much of a streamed reply was already queued
+#[derive(Default)]
+struct StreamState {
+    uuid: String,
+    // the part of the reply that was queued, a reply after a tool call starts over
+    spoken: String,
+    // stop keeps the rest of this reply quiet
+    muted: bool,
+}
+
+struct Job {
+    generation: u64,
+    backend: Arc<dyn SpeechBackend>,
+    text: String,
+}
+
+pub struct Speaker {
+    config: std::sync::Mutex<SpeechConfig>,
+    backend: std::sync::Mutex<Option<Arc<dyn SpeechBackend>>>,
+    // bumped by stop, queued sentences of an older generation are dropped
+    generation: Arc<AtomicU64>,
+    stream: std::sync::Mutex<StreamState>,
+    jobs: std::sync::Mutex<Sender<Job>>,
+}
+
+impl Speaker {
+    pub fn new(config: &SpeechConfig) -> Self {
+        let (tx, rx) = channel::<Job>();
+        let generation = Arc::new(AtomicU64::new(0));
+        let worker_generation = generation.clone();
+        // one thread speaks one sentence after the other
+        std::thread::spawn(move || {
+            for job in rx {
+                if job.generation != worker_generation.load(Ordering::SeqCst) {
+                    continue;
+                }
+                if let Err(e) = job.backend.speak(&job.text) {
+                    eprintln!("speak error {}", e);
+                }
+            }
+        });
+        let speaker = Self {
+            config: std::sync::Mutex::new(config.clone()),
+            backend: std::sync::Mutex::new(None),
+            generation,
+            stream: std::sync::Mutex::new(StreamState::default()),
+            jobs: std::sync::Mutex::new(tx),
+        };
+        if let Err(e) = speaker.set_config(config) {
+            eprintln!("load speech backend error {}", e);
+        }
+        speaker
+    }
+
+    // the config is kept even when the backend cannot be loaded, the error is for the settings page
+    pub fn set_config(&self, config: &SpeechConfig) -> Result<(), String> {
+        self.stop();
+        let backend = load_backend(config);
+        *self.config.lock().unwrap() = config.clone();
+        *self.backend.lock().unwrap() = backend.as_ref().ok().cloned();
+        backend.map(|_| ())
+    }
+
+    fn queue(&self, text: &str) -> Result<(), String> {
+        let text = clean_for_speech(text);
+        if !text.chars().any(char::is_alphanumeric) {
+            return Ok(());
+        }
+        let backend = self
+            .backend
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or("no speech backend is available, check the speech settings")?;
+        let job = Job { generation: self.generation.load(Ordering::SeqCst), backend, text };
+        self.jobs.lock().unwrap().send(job).map_err(|e| e.to_string())
+    }
+
+    // read a whole text, e.g. an older message, after stopping whatever was playing
+    pub fn say(&self, text: &str) -> Result<(), String> {
+        self.stop();
+        let mut sentences = Vec::new();
+        let mut start = 0;
+        for end in sentence_ends(text, 0, true) {
+            sentences.push(&text[start..end]);
+            start = end;
+        }
+        for sentence in sentences {
+            self.queue(sentence)?;
+        }
+        Ok(())
+    }
+
+    // called with the whole reply so far on every streamed token
+    pub fn stream(&self, uuid: &str, text: &str, is_final: bool) {
+        if !self.config.lock().unwrap().enabled {
+            return;
+        }
+        let mut state = self.stream.lock().unwrap();
+        if state.uuid != uuid {
+            *state = StreamState { uuid: uuid.to_string(), ..Default::default() };
+        } else if !text.starts_with(&state.spoken) {
+            // a stopped reply stays quiet after its tool calls too
+            state.spoken.clear();
+        }
+        if state.muted {
+            return;
+        }
+        let mut start = state.spoken.len();
+        for end in sentence_ends(text, start, is_final) {
+            if let Err(e) = self.queue(&text[start..end]) {
+                eprintln!("speak error {}", e);
+                break;
+            }
+            start = end;
+        }
+        state.spoken = text[..start].to_string();
+    }
+
+    pub fn stop(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.stream.lock().unwrap().muted = true;
+        if let Some(backend) = self.backend.lock().unwrap().as_ref() {
+            backend.stop();
+        }
+    }
+}
+
+pub fn speak_stream(app: &tauri::AppHandle, uuid: &str, text: &str, is_final: bool) {
+    let speaker: State<Speaker> = app.state();
+    speaker.stream(uuid, text, is_final);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentences(text: &str, is_final: bool) -> Vec<&str> {
+        let mut start = 0;
+        sentence_ends(text, 0, is_final)
+            .into_iter()
+            .map(|end| {
+                let sentence = &text[start..end];
+                start = end;
+                sentence
+            })
+            .collect()
+    }
+
+    #[test]
+    fn splits_after_marks_followed_by_space() {
+        assert_eq!(sentences("Hi there. Pi is 3.14! Ok? ", false), vec!["Hi there.", " Pi is 3.14!", " Ok?"]);
+        assert_eq!(sentences("one\ntwo", true), vec!["one\n", "two"]);
+    }
+
+    #[test]
+    fn abbreviations_and_initials_do_not_end_a_sentence() {
+        assert_eq!(
+            sentences("Ask Dr. Smith, e.g. about J. R. R. Tolkien. Then stop. ", false),
+            vec!["Ask Dr. Smith, e.g. about J. R. R. Tolkien.", " Then stop."]
+        );
+    }
+
+    #[test]
+    fn final_flush_speaks_the_unfinished_rest() {
+        // while streaming the last dot may still be followed by more of the number
+        assert_eq!(sentences("Done. It costs 3.", false), vec!["Done."]);
+        assert_eq!(sentences("Done. It costs 3.", true), vec!["Done.", " It costs 3."]);
+        assert_eq!(sentences("no mark at all", true), vec!["no mark at all"]);
+        assert!(sentence_ends("", 0, true).is_empty());
+    }
+
+    #[test]
+    fn only_text_after_from_is_split() {
+        let text = "First. Second. Third";
+        assert_eq!(sentence_ends(text, 6, false), vec![14]);
+        assert_eq!(sentence_ends(text, 14, true), vec![text.len()]);
+    }
+
+    #[test]
+    fn multibyte_text_splits_on_char_boundaries() {
+        assert_eq!(sentences("你好。今天很好！再见", true), vec!["你好。", "今天很好！", "再见"]);
+        assert_eq!(sentences("Ça va? Très bien.", true), vec!["Ça va?", " Très bien."]);
+    }
+
+    #[test]
+    fn stop_mutes_the_reply_after_a_tool_call() {
+        // piper without a model never loads, so nothing is played while testing
+        let speaker = Speaker::new(&SpeechConfig { enabled: true, engine: SpeechEngine::Piper, ..Default::default() });
+        speaker.stream("reply", "Let me check. ", false);
+        speaker.stop();
+        // what a working backend would have queued before the tool call
+        speaker.stream.lock().unwrap().spoken = "Let me check. ".to_string();
+        speaker.stream("reply", "The answer is 4. ", false);
+        assert!(speaker.stream.lock().unwrap().muted);
+        speaker.stream("next", "Hello. ", false);
+        assert!(!speaker.stream.lock().unwrap().muted);
+    }
+
+    #[test]
+    fn code_fences_never_end_a_sentence() {
+        assert_eq!(
+            sentences("Run this:\n```\nx = 1. y = 2.\n```\nDone. ", false),
+            vec!["Run this:\n", "```\nx = 1. y = 2.\n```\n", "Done."]
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use tauri::{async_runtime::Mutex, Manager as _, State};
+
+use crate::{
+    api_req::get_completion,
+    context::TokenCounter,
+    conversation::{save_current, Conversation, ConversationSummary},
+    profile::{select_profile, RouteTask},
+    serde_obj::{ConfigFile, SummaryEventPayload},
+    tokenizer::{tokenize_messages, MessageType, UserMessage},
+};
+
+const SUMMARY_PROMPT: &str = "You condense chat histories. Write a concise summary of the conversation below \
+so it can replace the original messages. Keep names, facts, decisions, code identifiers and open questions. \
+Do not add anything that was not said. Reply with the summary only.";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SummaryConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // summarize once the history sent to the model is bigger than this
+    #[serde(default = "default_threshold_tokens")]
+    pub threshold_tokens: usize,
+    // the newest turns are always sent as they are
+    #[serde(default = "default_keep_recent_turns")]
+    pub keep_recent_turns: usize,
+}
+
+fn default_threshold_tokens() -> usize {
+    4096
+}
+
+fn default_keep_recent_turns() -> usize {
+    4
+}
+
+impl Default for SummaryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold_tokens: default_threshold_tokens(),
+            keep_recent_turns: default_keep_recent_turns(),
+        }
+    }
+}
+
+// index of the first message that has to stay, or None when there is nothing to condense
+fn find_split(messages: &[MessageType], covered: usize, keep_recent_turns: usize) -> Option<usize> {
+    let user_idx: Vec<usize> = messages
+        .iter()
+        .enumerate()
+        .skip(covered)
+        .filter(|(_, m)| matches!(m, MessageType::User(_)))
+        .map(|(idx, _)| idx)
+        .collect();
+    if user_idx.len() <= keep_recent_turns.max(1) {
+        return None;
+    }
+    Some(user_idx[user_idx.len() - keep_recent_turns.max(1)])
+}
+
+// the anchor ties the summary to this branch, an edit above it makes the summary stale
+fn new_summary(conversation: &Conversation, split: usize, content: String) -> ConversationSummary {
+    ConversationSummary { content, covered: split, anchor: Some(conversation.active_path()[split - 1]) }
+}
+
+fn build_transcript(previous: Option<&ConversationSummary>, messages: &[MessageType]) -> String {
+    let mut transcript = String::new();
+    if let Some(previous) = previous {
+        transcript.push_str("Summary so far:\n");
+        transcript.push_str(&previous.content);
+        transcript.push_str("\n\n");
+    }
+    for message in messages {
+        match message {
+            MessageType::User(m) => transcript.push_str(&format!("User: {}\n", m.content)),
+            MessageType::Assistant(m) => transcript.push_str(&format!("Assistant: {}\n", m.content)),
+            MessageType::ToolResponse(m) => {
+                transcript.push_str(&format!("Tool {} returned: {}\n", m.name, m.content))
+            }
+            _ => {}
+        }
+    }
+    transcript
+}
+
+pub async fn maybe_summarize(app: &tauri::AppHandle, messages_uuid: &str) {
+    let config_s: State<Arc<Mutex<ConfigFile>>> = app.state();
+    let config = config_s.lock().await.summary.clone();
+    if !config.enabled {
+        return;
+    }
+    let conversation_s: State<Arc<Mutex<Conversation>>> = app.state();
+    let conversation = conversation_s.lock().await.clone();
+    let counter: State<Arc<dyn TokenCounter>> = app.state();
+    let size = counter.count(&tokenize_messages(conversation.prompt_messages(), None, &[]));
+    if size < config.threshold_tokens {
+        return;
+    }
+    // a summary from another branch is ignored by prompt_messages, so start over in that case
+    let messages = conversation.messages();
+    let previous = conversation.active_summary();
+    let covered = previous.map(|s| s.covered).unwrap_or(0);
+    let Some(split) = find_split(&messages, covered, config.keep_recent_turns) else {
+        return;
+    };
+    let transcript = build_transcript(previous, &messages[covered..split]);
+    let profile = select_profile(app, RouteTask::Summary).await;
+    let content = match get_completion(
+        app,
+        profile,
+        vec![MessageType::User(UserMessage::new(transcript))],
+        Some(SUMMARY_PROMPT.to_string()),
+    )
+    .await
+    {
+        Ok(content) if !content.trim().is_empty() => content.trim().to_string(),
+        Ok(_) => return,
+        Err(e) => {
+            eprintln!("summarize conversation error {}", e);
+            return;
+        }
+    };
+    let summary = new_summary(&conversation, split, content);
+    {
+        let mut current = conversation_s.lock().await;
+        // the user may have started a new chat while the model was busy
+        if current.id != conversation.id {
+            return;
+        }
+        current.summary = Some(summary.clone());
+    }
+    save_current(app).await;
+    let _ = app.emit_all(
+        "summary",
+        SummaryEventPayload {
+            uuid: messages_uuid.to_string(),
+            content: summary.content,
+            covered: summary.covered,
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::{AssistantMessage, ToolCall, ToolResponse};
+
+    fn user(content: &str) -> MessageType {
+        MessageType::User(UserMessage::new(content.to_string()))
+    }
+
+    fn assistant(content: &str) -> MessageType {
+        MessageType::Assistant(AssistantMessage { content: content.to_string(), model: None })
+    }
+
+    fn tool_call() -> MessageType {
+        MessageType::ToolCall(ToolCall { content: r#"[{"name":"calculate","arguments":{},"call_id":"1"}]"#.to_string() })
+    }
+
+    fn tool_response(content: &str) -> MessageType {
+        MessageType::ToolResponse(ToolResponse {
+            content: content.into(),
+            call_id: Some("1".to_string()),
+            name: "calculate".to_string(),
+            status: Default::default(),
+            duration_ms: 0,
+        })
+    }
+
+    #[test]
+    fn split_lands_on_a_user_message() {
+        let messages = vec![
+            user("one"),
+            tool_call(),
+            tool_response("1"),
+            assistant("a"),
+            user("two"),
+            tool_call(),
+            tool_response("2"),
+            assistant("b"),
+            user("three"),
+            assistant("c"),
+        ];
+        // a tool call and its response are never cut apart, both stay on one side
+        assert_eq!(find_split(&messages, 0, 1), Some(8));
+        assert_eq!(find_split(&messages, 0, 2), Some(4));
+        assert_eq!(find_split(&messages, 0, 3), None);
+        // zero recent turns still keeps the last one
+        assert_eq!(find_split(&messages, 0, 0), Some(8));
+    }
+
+    #[test]
+    fn covered_messages_are_not_split_again() {
+        let messages = vec![user("one"), assistant("a"), user("two"), assistant("b"), user("three")];
+        assert_eq!(find_split(&messages, 2, 1), Some(4));
+        assert_eq!(find_split(&messages, 4, 1), None);
+    }
+
+    #[test]
+    fn transcript_keeps_the_previous_summary_and_tool_results() {
+        let previous = ConversationSummary { content: "They met.".to_string(), covered: 2, anchor: None };
+        let transcript = build_transcript(Some(&previous), &[user("2+2?"), tool_call(), tool_response("4"), assistant("4")]);
+        assert_eq!(
+            transcript,
+            "Summary so far:\nThey met.\n\nUser: 2+2?\nTool calculate returned: \"4\"\nAssistant: 4\n"
+        );
+    }
+
+    #[test]
+    fn summary_is_anchored_to_its_branch() {
+        let mut conversation = Conversation::default();
+        let first = conversation.push(user("one"));
+        conversation.push(assistant("a"));
+        conversation.push(user("two"));
+        conversation.push(assistant("b"));
+        let split = find_split(&conversation.messages(), 0, 1).unwrap();
+        conversation.summary = Some(new_summary(&conversation, split, "one and a".to_string()));
+        let prompt = conversation.prompt_messages();
+        assert_eq!(prompt.len(), 3);
+        assert!(matches!(&prompt[1], MessageType::User(m) if m.content == "two"));
+        // editing a summarized message starts a branch the summary does not describe
+        conversation.add_child(None, user("one, edited"));
+        assert!(conversation.active_summary().is_none());
+        conversation.select(first);
+        assert!(conversation.active_summary().is_some());
+    }
+}
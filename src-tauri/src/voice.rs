@@ -0,0 +1,413 @@
+use std::{
+    io::Read,
+    path::Path,
+    sync::{
+        mpsc::{channel, Sender},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use cpal::traits::{DeviceTrait as _, HostTrait as _, StreamTrait as _};
+use serde::{Deserialize, Serialize};
+use tauri::{async_runtime::Mutex, Manager as _, State};
+
+use crate::serde_obj::{ConfigFile, VoiceEventPayload, VoiceStatus};
+
+// whisper models are trained on 16 kHz mono audio
+pub const SAMPLE_RATE: u32 = 16_000;
+// shorter clips are a click on the ball, not speech
+const MIN_SAMPLES: usize = SAMPLE_RATE as usize / 4;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VoiceConfig {
+    // a whisper.cpp ggml model, e.g. ggml-base.en.bin
+    #[serde(default)]
+    pub model_path: Option<String>,
+    // "auto" or a language code like "en"
+    #[serde(default = "default_language")]
+    pub language: String,
+    // recording stops by itself after this many seconds
+    #[serde(default = "default_max_seconds")]
+    pub max_seconds: u32,
+}
+
+impl VoiceConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(path) = self.model_path.as_ref().filter(|p| !Path::new(p).is_file()) {
+            return Err(format!("model {} not found", path));
+        }
+        Ok(())
+    }
+}
+
+fn default_language() -> String {
+    "auto".to_string()
+}
+
+fn default_max_seconds() -> u32 {
+    60
+}
+
+impl Default for VoiceConfig {
+    fn default() -> Self {
+        Self {
+            model_path: None,
+            language: default_language(),
+            max_seconds: default_max_seconds(),
+        }
+    }
+}
+
+pub trait Transcriber: Send + Sync {
+    // 16 kHz mono samples in -1.0..1.0
+    fn transcribe(&self, samples: &[f32]) -> Result<String, String>;
+}
+
+pub struct WhisperTranscriber {
+    context: whisper_rs::WhisperContext,
+    language: String,
+}
+
+impl WhisperTranscriber {
+    pub fn load(config: &VoiceConfig) -> Result<Self, String> {
+        let path = config.model_path.as_ref().ok_or("voice.model_path is not set")?;
+        let context = whisper_rs::WhisperContext::new_with_params(path, whisper_rs::WhisperContextParameters::default())
+            .map_err(|e| format!("load whisper model {}: {}", path, e))?;
+        Ok(Self { context, language: config.language.clone() })
+    }
+}
+
+impl Transcriber for WhisperTranscriber {
+    fn transcribe(&self, samples: &[f32]) -> Result<String, String> {
+        let mut state = self.context.create_state().map_err(|e| e.to_string())?;
+        let mut params = whisper_rs::FullParams::new(whisper_rs::SamplingStrategy::Greedy { best_of: 1 });
+        params.set_language(Some(&self.language));
+        params.set_print_progress(false);
+        params.set_print_realtime(false);
+        params.set_print_special(false);
+        params.set_print_timestamps(false);
+        let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4).min(8);
+        params.set_n_threads(threads as i32);
+        state.full(params, samples).map_err(|e| e.to_string())?;
+        let segments = state.full_n_segments().map_err(|e| e.to_string())?;
+        let mut text = String::new();
+        for segment in 0..segments {
+            text.push_str(&state.full_get_segment_text(segment).map_err(|e| e.to_string())?);
+        }
+        Ok(text)
+    }
+}
+
+pub fn to_mono(samples: &[f32], channels: u16) -> Vec<f32> {
+    if channels <= 1 {
+        return samples.to_vec();
+    }
+    samples
+        .chunks(channels as usize)
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect()
+}
+
+// linear interpolation is enough for speech, whisper only looks at the 80 mel bands
+pub fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let ratio = from_rate as f64 / to_rate as f64;
+    let len = (samples.len() as f64 / ratio).floor() as usize;
+    (0..len)
+        .map(|idx| {
+            let pos = idx as f64 * ratio;
+            let left = pos.floor() as usize;
+            let right = (left + 1).min(samples.len() - 1);
+            let frac = (pos - left as f64) as f32;
+            samples[left] * (1.0 - frac) + samples[right] * frac
+        })
+        .collect()
+}
+
+// any captured or recorded audio to what the transcriber expects
+pub fn prepare(samples: &[f32], sample_rate: u32, channels: u16) -> Vec<f32> {
+    resample(&to_mono(samples, channels), sample_rate, SAMPLE_RATE)
+}
+
+pub fn read_wav<R: Read>(reader: R) -> Result<Vec<f32>, String> {
+    let reader = hound::WavReader::new(reader).map_err(|e| e.to_string())?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .into_samples::<f32>()
+            .collect::<Result<_, _>>()
+            .map_err(|e| e.to_string())?,
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .into_samples::<i32>()
+                .map(|s| s.map(|s| s as f32 / scale))
+                .collect::<Result<_, _>>()
+                .map_err(|e| e.to_string())?
+        }
+    };
+    Ok(prepare(&samples, spec.sample_rate, spec.channels))
+}
+
+// whisper marks silence and noise as [BLANK_AUDIO], (music) and the like
+pub fn clean_transcript(text: &str) -> String {
+    let mut cleaned = String::new();
+    let mut depth = 0;
+    for c in text.chars() {
+        match c {
+            '[' | '(' => depth += 1,
+            ']' | ')' if depth > 0 => depth -= 1,
+            _ if depth == 0 => cleaned.push(c),
+            _ => {}
+        }
+    }
+    cleaned.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+pub fn transcribe_samples(transcriber: &dyn Transcriber, samples: &[f32]) -> Result<String, String> {
+    if samples.len() < MIN_SAMPLES {
+        return Err("the recording is too short".to_string());
+    }
+    let text = clean_transcript(&transcriber.transcribe(samples)?);
+    if text.is_empty() {
+        return Err("no speech was recognized".to_string());
+    }
+    Ok(text)
+}
+
+pub fn transcribe_wav(transcriber: &dyn Transcriber, path: &Path) -> Result<String, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    transcribe_samples(transcriber, &read_wav(std::io::BufReader::new(file))?)
+}
+
+struct Captured {
+    samples: Vec<f32>,
+    sample_rate: u32,
+    channels: u16,
+}
+
+// cpal streams cannot move between threads, the stream lives on its own thread until stop
+struct Recording {
+    stop: Sender<()>,
+    thread: JoinHandle<Result<Captured, String>>,
+}
+
+fn capture(stop: std::sync::mpsc::Receiver<()>, max: Duration) -> Result<Captured, String> {
+    let device = cpal::default_host()
+        .default_input_device()
+        .ok_or("no microphone was found")?;
+    let config = device.default_input_config().map_err(|e| e.to_string())?;
+    let sample_rate = config.sample_rate().0;
+    let channels = config.channels();
+    let samples = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let sink = samples.clone();
+    let on_error = |e: cpal::StreamError| eprintln!("microphone error {}", e);
+    let stream = match config.sample_format() {
+        cpal::SampleFormat::F32 => device.build_input_stream(
+            &config.into(),
+            move |data: &[f32], _: &_| sink.lock().unwrap().extend_from_slice(data),
+            on_error,
+            None,
+        ),
+        cpal::SampleFormat::I16 => device.build_input_stream(
+            &config.into(),
+            move |data: &[i16], _: &_| sink.lock().unwrap().extend(data.iter().map(|s| *s as f32 / 32768.0)),
+            on_error,
+            None,
+        ),
+        cpal::SampleFormat::U16 => device.build_input_stream(
+            &config.into(),
+            move |data: &[u16], _: &_| {
+                sink.lock().unwrap().extend(data.iter().map(|s| (*s as f32 - 32768.0) / 32768.0))
+            },
+            on_error,
+            None,
+        ),
+        format => return Err(format!("unsupported microphone format {}", format)),
+    }
+    .map_err(|e| e.to_string())?;
+    stream.play().map_err(|e| e.to_string())?;
+    // stop or timeout, a dropped sender also ends the recording
+    let _ = stop.recv_timeout(max);
+    drop(stream);
+    let samples = std::mem::take(&mut *samples.lock().unwrap());
+    Ok(Captured { samples, sample_rate, channels })
+}
+
+#[derive(Default)]
+pub struct VoiceInput {
+    recording: std::sync::Mutex<Option<Recording>>,
+    // the model is loaded on the first transcription and kept
+    transcriber: std::sync::Mutex<Option<Arc<dyn Transcriber>>>,
+}
+
+impl VoiceInput {
+    pub fn is_recording(&self) -> bool {
+        self.recording.lock().unwrap().is_some()
+    }
+
+    // after the model path or language changed
+    pub fn reset(&self) {
+        *self.transcriber.lock().unwrap() = None;
+    }
+
+    fn transcriber(&self, config: &VoiceConfig) -> Result<Arc<dyn Transcriber>, String> {
+        let mut transcriber = self.transcriber.lock().unwrap();
+        if let Some(transcriber) = transcriber.as_ref() {
+            return Ok(transcriber.clone());
+        }
+        let loaded: Arc<dyn Transcriber> = Arc::new(WhisperTranscriber::load(config)?);
+        *transcriber = Some(loaded.clone());
+        Ok(loaded)
+    }
+}
+
+fn emit(app: &tauri::AppHandle, status: VoiceStatus, text: Option<String>) {
+    let _ = app.emit_all("voice-input", VoiceEventPayload { status, text });
+}
+
+pub async fn start(app: &tauri::AppHandle) -> Result<(), String> {
+    let config_s: State<Arc<Mutex<ConfigFile>>> = app.state();
+    let config = config_s.lock().await.voice.clone();
+    if config.model_path.is_none() {
+        return Err("set a whisper model in the settings first".to_string());
+    }
+    let voice: State<VoiceInput> = app.state();
+    let mut recording = voice.recording.lock().unwrap();
+    if recording.is_some() {
+        return Ok(());
+    }
+    let (stop, stop_rx) = channel();
+    let max = Duration::from_secs(config.max_seconds.max(1) as u64);
+    let thread_app = app.clone();
+    let thread = std::thread::spawn(move || {
+        let captured = capture(stop_rx, max);
+        // the time limit ends the recording like a second press
+        let voice: State<VoiceInput> = thread_app.state();
+        if voice.is_recording() {
+            let app = thread_app.clone();
+            tauri::async_runtime::spawn(async move { finish(&app).await });
+        }
+        captured
+    });
+    *recording = Some(Recording { stop, thread });
+    drop(recording);
+    emit(app, VoiceStatus::Recording, None);
+    Ok(())
+}
+
+// the transcript goes to the chat window, which sends it with new_message like typed text
+pub async fn finish(app: &tauri::AppHandle) {
+    let voice: State<VoiceInput> = app.state();
+    let Some(recording) = voice.recording.lock().unwrap().take() else {
+        return;
+    };
+    let _ = recording.stop.send(());
+    emit(app, VoiceStatus::Transcribing, None);
+    let config_s: State<Arc<Mutex<ConfigFile>>> = app.state();
+    let config = config_s.lock().await.voice.clone();
+    let task_app = app.clone();
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let captured = recording.thread.join().map_err(|_| "recording thread panicked".to_string())??;
+        let samples = prepare(&captured.samples, captured.sample_rate, captured.channels);
+        let voice: State<VoiceInput> = task_app.state();
+        transcribe_samples(voice.transcriber(&config)?.as_ref(), &samples)
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|r| r);
+    match result {
+        Ok(text) => emit(app, VoiceStatus::Done, Some(text)),
+        Err(e) => emit(app, VoiceStatus::Error, Some(e)),
+    }
+}
+
+// push to talk for the hotkey: first press records, second press sends
+pub async fn toggle(app: &tauri::AppHandle) {
+    let voice: State<VoiceInput> = app.state();
+    if voice.is_recording() {
+        finish(app).await;
+        return;
+    }
+    crate::create_main_window(app.clone());
+    if let Err(e) = start(app).await {
+        emit(app, VoiceStatus::Error, Some(e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // hears the length and loudness of the audio it gets
+    struct FakeTranscriber;
+
+    impl Transcriber for FakeTranscriber {
+        fn transcribe(&self, samples: &[f32]) -> Result<String, String> {
+            let peak = samples.iter().fold(0f32, |max, s| max.max(s.abs()));
+            if peak < 0.01 {
+                return Ok("[BLANK_AUDIO]".to_string());
+            }
+            Ok(format!(" {} samples (speaking) ", samples.len()))
+        }
+    }
+
+    fn wav_fixture(spec: hound::WavSpec, seconds: f32, amplitude: f32) -> Vec<u8> {
+        let mut buf = Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut buf, spec).unwrap();
+        let frames = (spec.sample_rate as f32 * seconds) as usize;
+        for frame in 0..frames {
+            let value = amplitude * (frame as f32 * 440.0 * std::f32::consts::TAU / spec.sample_rate as f32).sin();
+            for _ in 0..spec.channels {
+                match spec.sample_format {
+                    hound::SampleFormat::Float => writer.write_sample(value).unwrap(),
+                    hound::SampleFormat::Int => writer.write_sample((value * i16::MAX as f32) as i16).unwrap(),
+                }
+            }
+        }
+        writer.finalize().unwrap();
+        buf.into_inner()
+    }
+
+    fn spec(channels: u16, sample_rate: u32, sample_format: hound::SampleFormat) -> hound::WavSpec {
+        let bits_per_sample = if sample_format == hound::SampleFormat::Float { 32 } else { 16 };
+        hound::WavSpec { channels, sample_rate, bits_per_sample, sample_format }
+    }
+
+    #[test]
+    fn wav_is_converted_to_16k_mono() {
+        let wav = wav_fixture(spec(2, 44_100, hound::SampleFormat::Int), 1.0, 0.5);
+        let samples = read_wav(Cursor::new(wav)).unwrap();
+        assert_eq!(samples.len(), SAMPLE_RATE as usize);
+        let peak = samples.iter().fold(0f32, |max, s| max.max(s.abs()));
+        assert!((peak - 0.5).abs() < 0.01, "{}", peak);
+
+        let wav = wav_fixture(spec(1, SAMPLE_RATE, hound::SampleFormat::Float), 0.5, 0.25);
+        assert_eq!(read_wav(Cursor::new(wav)).unwrap().len(), SAMPLE_RATE as usize / 2);
+    }
+
+    #[test]
+    fn transcribes_wav_fixture() {
+        let wav = wav_fixture(spec(1, 8_000, hound::SampleFormat::Int), 2.0, 0.5);
+        let samples = read_wav(Cursor::new(wav)).unwrap();
+        assert_eq!(transcribe_samples(&FakeTranscriber, &samples).unwrap(), "32000 samples");
+    }
+
+    #[test]
+    fn silence_and_clicks_are_errors() {
+        let silence = read_wav(Cursor::new(wav_fixture(spec(1, SAMPLE_RATE, hound::SampleFormat::Int), 1.0, 0.0))).unwrap();
+        assert!(transcribe_samples(&FakeTranscriber, &silence).is_err());
+        let click = read_wav(Cursor::new(wav_fixture(spec(1, SAMPLE_RATE, hound::SampleFormat::Int), 0.1, 0.5))).unwrap();
+        assert!(transcribe_samples(&FakeTranscriber, &click).is_err());
+    }
+
+    #[test]
+    fn whisper_annotations_are_removed() {
+        assert_eq!(clean_transcript(" Hello [BLANK_AUDIO] world (music) "), "Hello world");
+    }
+}
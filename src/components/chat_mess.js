@@ -18,6 +18,96 @@ class Message extends HTMLElement {
         this.innerHTML = "<div class=\"loader\"></div>";
         return this;
     }
+
+    // edit / regenerate / branch / read aloud buttons, the page listens for the bubbling events
+    actions(entry) {
+        if (entry.node == null) return this;
+        let bar = document.createElement("div");
+        bar.setAttribute("class", "message-actions");
+        let fire = (name, detail) => this.dispatchEvent(new CustomEvent(name, { bubbles: true, detail: { node: entry.node, element: this, ...detail } }));
+        if (entry.branch_count > 1) {
+            let prev = document.createElement("button");
+            prev.textContent = "‹";
+            prev.disabled = entry.branch_index == 0;
+            prev.addEventListener("click", () => fire("branch", { offset: -1 }));
+            let label = document.createElement("span");
+            label.textContent = `${entry.branch_index + 1}/${entry.branch_count}`;
+            let next = document.createElement("button");
+            next.textContent = "›";
+            next.disabled = entry.branch_index + 1 >= entry.branch_count;
+            next.addEventListener("click", () => fire("branch", { offset: 1 }));
+            bar.append(prev, label, next);
+        }
+        let action = document.createElement("button");
+        if (entry.is_user) {
+            action.textContent = "✎";
+            action.title = "Edit";
+            action.addEventListener("click", () => fire("edit", { content: entry.content }));
+        } else {
+            action.textContent = "↻";
+            action.title = "Regenerate";
+            action.addEventListener("click", () => fire("regenerate", {}));
+            let speak = document.createElement("button");
+            speak.textContent = "🔊";
+            speak.title = "Read aloud";
+            speak.addEventListener("click", () => fire("speak", { content: entry.content }));
+            bar.appendChild(speak);
+        }
+        bar.appendChild(action);
+        this.appendChild(bar);
+        return this;
+    }
+}
+
+class ToolCard extends HTMLElement {
+    constructor() {
+        super();
+    }
+
+    update(tool) {
+        this.setAttribute("class", "markdown-body message tool-card");
+        let details = document.createElement("details");
+        let summary = document.createElement("summary");
+        let status = { running: "⏳", success: "✅", error: "❌" }[tool.status] || "";
+        summary.textContent = `${status} ${tool.name}`;
+        if (tool.duration_ms != null) {
+            summary.textContent += ` (${tool.duration_ms} ms)`;
+        }
+        details.appendChild(summary);
+        let args = document.createElement("pre");
+        args.textContent = JSON.stringify(tool.arguments, null, 2);
+        details.appendChild(args);
+        if (tool.result != null) {
+            let result = document.createElement("pre");
+            result.textContent = typeof tool.result === "string" ? tool.result : JSON.stringify(tool.result, null, 2);
+            details.appendChild(result);
+        }
+        if (this.firstChild && this.firstChild.open) {
+            details.open = true;
+        }
+        this.replaceChildren(details);
+        return this;
+    }
+}
+
+class SummaryNote extends HTMLElement {
+    constructor() {
+        super();
+    }
+
+    async init(summary) {
+        this.setAttribute("class", "markdown-body message summary-note");
+        let details = document.createElement("details");
+        let title = document.createElement("summary");
+        title.textContent = "📝 Earlier messages were summarized";
+        let content = document.createElement("div");
+        content.innerHTML = await invoke('md_to_html', { text: summary });
+        details.append(title, content);
+        this.replaceChildren(details);
+        return this;
+    }
 }
 
-window.customElements.define("chat-message", Message);
\ No newline at end of file
+window.customElements.define("chat-message", Message);
+window.customElements.define("chat-tool", ToolCard);
+window.customElements.define("chat-summary", SummaryNote);
\ No newline at end of file